@@ -1,102 +1,275 @@
-use crate::bio_format::bam::{from_bam_inner, from_sam_inner};
-use crate::bio_format::bcf::{from_bcf_inner, from_vcf_inner};
-use crate::bio_format::bed::from_bed_inner;
-use crate::bio_format::cram::from_cram_inner;
-use crate::bio_format::fasta::{from_fasta_inner, from_fastq_inner, nuon_to_fasta, nuon_to_fastq};
-use crate::bio_format::gfa::from_gfa_inner;
-use crate::bio_format::gff::from_gff_inner;
+use crate::bio_format::bam::{
+    from_bam_inner, from_sam_inner, nuon_to_bam, nuon_to_sam, query_bam_inner,
+};
+use crate::bio_format::bcf::{
+    from_bcf_inner, from_vcf_inner, nuon_to_bcf, nuon_to_vcf, query_vcf_inner,
+};
+use crate::bio_format::bed::{from_bed_inner, nuon_to_bed, query_bed_inner};
+use crate::bio_format::cram::{from_cram_inner, query_cram_inner};
+use crate::bio_format::fasta::{
+    faidx_inner, from_fasta_inner, from_fastq_inner, nuon_to_fasta, nuon_to_fastq,
+};
+use crate::bio_format::gfa::{from_gfa_inner, nuon_to_gfa};
+use crate::bio_format::gff::{from_gff_inner, nuon_to_gff, query_gff_inner};
+use crate::bio_format::index::index_inner;
 use crate::bio_format::Compression;
 use nu_plugin::EvaluatedCall;
 use nu_protocol::LabeledError;
-use nu_protocol::Value;
+use nu_protocol::{PipelineData, Signals, Value};
+use std::path::PathBuf;
 
 /// We implement a bunch of parsers on the `Bio` struct.
 pub struct Bio;
 
+// the parsers are named after the nushell commands, e.g. `from_bam` for `from bam`.
+#[allow(clippy::wrong_self_convention)]
 impl Bio {
     /// Parsing a fasta into Nushell.
     pub fn from_fasta(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        input: PipelineData,
+        gz: Compression,
+    ) -> Result<PipelineData, LabeledError> {
+        let value_records = from_fasta_inner(call, signals, input, gz)?;
+
+        Ok(PipelineData::ListStream(value_records, None))
+    }
+
+    /// Structured data to fasta, optionally BGZF compressed.
+    pub fn to_fasta(
         &self,
         call: &EvaluatedCall,
         input: &Value,
         gz: Compression,
     ) -> Result<Value, LabeledError> {
-        let value_records = from_fasta_inner(call, input, gz)?;
-
-        Ok(Value::list(value_records, call.head))
+        nuon_to_fasta(call, input, gz)
     }
 
-    pub fn to_fasta(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        nuon_to_fasta(call, input)
+    /// Fetch regions from an indexed fasta.
+    pub fn faidx(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        path: PathBuf,
+        regions: Vec<String>,
+    ) -> Result<PipelineData, LabeledError> {
+        faidx_inner(call, signals, path, regions).map(|e| PipelineData::ListStream(e, None))
     }
 
     /// Parsing a fastq into Nushell.
     pub fn from_fastq(
         &self,
         call: &EvaluatedCall,
-        input: &Value,
+        signals: &Signals,
+        input: PipelineData,
         gz: Compression,
-    ) -> Result<Value, LabeledError> {
-        let value_records = from_fastq_inner(call, input, gz)?;
-        Ok(Value::list(value_records, call.head))
+    ) -> Result<PipelineData, LabeledError> {
+        let value_records = from_fastq_inner(call, signals, input, gz)?;
+        Ok(PipelineData::ListStream(value_records, None))
     }
 
-    /// Structured data to fastq
-    pub fn to_fastq(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        nuon_to_fastq(call, input)
+    /// Structured data to fastq, optionally BGZF compressed.
+    pub fn to_fastq(
+        &self,
+        call: &EvaluatedCall,
+        input: &Value,
+        gz: Compression,
+    ) -> Result<Value, LabeledError> {
+        nuon_to_fastq(call, input, gz)
     }
 
     /// These B(S)AM functions are quite slow at the moment.
-    pub fn from_bam(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        from_bam_inner(call, input)
+    pub fn from_bam(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        from_bam_inner(call, signals, input)
     }
     /// These B(S)AM functions are quite slow at the moment.
-    pub fn from_sam(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        from_sam_inner(call, input)
+    pub fn from_sam(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        from_sam_inner(call, signals, input)
+    }
+
+    /// Query the alignments of an indexed BAM that overlap a region.
+    pub fn query_bam(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        path: PathBuf,
+        region: &str,
+    ) -> Result<PipelineData, LabeledError> {
+        query_bam_inner(call, signals, path, region).map(|e| PipelineData::ListStream(e, None))
     }
 
-    /// Parse a CRAM file.
-    pub fn from_cram(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        from_cram_inner(call, input)
+    /// Structured data to BAM
+    pub fn to_bam(
+        &self,
+        call: &EvaluatedCall,
+        input: &Value,
+        compression_level: Option<i64>,
+    ) -> Result<Value, LabeledError> {
+        nuon_to_bam(call, input, compression_level)
+    }
+
+    /// Structured data to SAM
+    pub fn to_sam(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_sam(call, input)
+    }
+
+    /// Parse a CRAM file, optionally against a reference fasta.
+    pub fn from_cram(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        input: PipelineData,
+        reference: Option<PathBuf>,
+    ) -> Result<PipelineData, LabeledError> {
+        from_cram_inner(call, signals, input, reference)
+    }
+
+    /// Query the alignments of an indexed CRAM that overlap a region.
+    pub fn query_cram(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        path: PathBuf,
+        region: &str,
+        reference: Option<PathBuf>,
+    ) -> Result<PipelineData, LabeledError> {
+        query_cram_inner(call, signals, path, region, reference)
+            .map(|e| PipelineData::ListStream(e, None))
     }
 
     /// Parse a BCF.
     pub fn from_bcf(
         &self,
         call: &EvaluatedCall,
-        input: &Value,
+        signals: &Signals,
+        input: PipelineData,
         gz: Compression,
-    ) -> Result<Value, LabeledError> {
-        from_bcf_inner(call, input, gz)
+    ) -> Result<PipelineData, LabeledError> {
+        from_bcf_inner(call, signals, input, gz)
+    }
+
+    /// Structured data to BCF.
+    pub fn to_bcf(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_bcf(call, input)
     }
     /// Parse a VCF.
     pub fn from_vcf(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        input: PipelineData,
+        gz: Compression,
+    ) -> Result<PipelineData, LabeledError> {
+        from_vcf_inner(call, signals, input, gz)
+    }
+
+    /// Structured data to VCF, optionally BGZF compressed.
+    pub fn to_vcf(
         &self,
         call: &EvaluatedCall,
         input: &Value,
         gz: Compression,
     ) -> Result<Value, LabeledError> {
-        from_vcf_inner(call, input, gz)
+        nuon_to_vcf(call, input, gz)
+    }
+
+    /// Query the variants of a tabix indexed VCF that overlap a region.
+    pub fn query_vcf(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        path: PathBuf,
+        region: &str,
+    ) -> Result<PipelineData, LabeledError> {
+        query_vcf_inner(call, signals, path, region).map(|e| PipelineData::ListStream(e, None))
     }
 
     /// Parse a GFF.
-    pub fn from_gff(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-        let value_records = from_gff_inner(call, input)?;
-        Ok(Value::list(value_records, call.head))
+    pub fn from_gff(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        from_gff_inner(call, signals, input)
+    }
+
+    /// Structured data to GFF3.
+    pub fn to_gff(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_gff(call, input)
+    }
+
+    /// Query the features of a tabix indexed GFF that overlap a region.
+    pub fn query_gff(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        path: PathBuf,
+        region: &str,
+    ) -> Result<PipelineData, LabeledError> {
+        query_gff_inner(call, signals, path, region).map(|e| PipelineData::ListStream(e, None))
     }
 
     /// Parse a GFA.
     pub fn from_gfa(
         &self,
         call: &EvaluatedCall,
-        input: &Value,
+        input: PipelineData,
         gz: Compression,
     ) -> Result<Value, LabeledError> {
         from_gfa_inner(call, input, gz)
     }
 
+    /// Structured data to GFA.
+    pub fn to_gfa(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_gfa(call, input)
+    }
+
     /// Parse a BED.
-    pub fn from_bed(&self, call: &EvaluatedCall, input: Value) -> Result<Value, LabeledError> {
-        from_bed_inner(call, input).map(|e| Value::list(e, call.head))
+    pub fn from_bed(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        from_bed_inner(call, signals, input).map(|e| PipelineData::ListStream(e, None))
+    }
+
+    /// Structured data to BED.
+    pub fn to_bed(&self, call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+        nuon_to_bed(call, input)
+    }
+
+    /// Query the intervals of a tabix indexed BED that overlap a region.
+    pub fn query_bed(
+        &self,
+        call: &EvaluatedCall,
+        signals: &Signals,
+        path: PathBuf,
+        region: &str,
+    ) -> Result<PipelineData, LabeledError> {
+        query_bed_inner(call, signals, path, region).map(|e| PipelineData::ListStream(e, None))
+    }
+
+    /// Build the index of a file.
+    pub fn index(
+        &self,
+        call: &EvaluatedCall,
+        path: PathBuf,
+        reference: Option<PathBuf>,
+    ) -> Result<Value, LabeledError> {
+        index_inner(call, path, reference)
     }
 }
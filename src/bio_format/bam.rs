@@ -1,12 +1,22 @@
-use crate::bio_format::SpanExt;
+use crate::bio_format::{
+    find_index, header_body_output, input_reader, parse_region, stream_values, Sink, SpanExt,
+};
 use noodles_bam as bam;
+use noodles_bgzf as bgzf;
+use noodles_csi as csi;
 use noodles_sam as sam;
+use noodles_sam::alignment::io::Write as _;
+use noodles_sam::alignment::record::cigar::op::Kind;
+use noodles_sam::alignment::record::data::field::{
+    value::Array as DataArray, Tag, Value as DataValue,
+};
 use noodles_sam::alignment::Record as SAMRecord;
 use noodles_sam::header::record::value::Map;
 use nu_plugin::EvaluatedCall;
 use nu_protocol::LabeledError;
-use nu_protocol::{record, Record, Value};
-use std::io::{BufReader, Cursor};
+use nu_protocol::{record, ListStream, PipelineData, Record, Signals, Value};
+use std::io::{self, BufReader};
+use std::path::PathBuf;
 
 /// Columns in a BAM/SAM file
 pub const BAM_COLUMNS: &[&str] = &[
@@ -33,6 +43,91 @@ pub const HEADER_COLUMNS: &[&str] = &[
     "comments",
 ];
 
+/// Standard @HD tags, and the names we give them.
+const HD_TAGS: &[(&[u8; 2], &str)] = &[
+    (b"SO", "sorting_order"),
+    (b"GO", "grouping"),
+    (b"SS", "sub_sort_order"),
+];
+
+/// Standard @SQ tags, and the names we give them.
+const SQ_TAGS: &[(&[u8; 2], &str)] = &[
+    (b"AH", "alternate_locus"),
+    (b"AN", "alternate_names"),
+    (b"AS", "assembly_id"),
+    (b"DS", "description"),
+    (b"M5", "md5"),
+    (b"SP", "species"),
+    (b"TP", "molecule_topology"),
+    (b"UR", "uri"),
+];
+
+/// Standard @RG tags, and the names we give them.
+const RG_TAGS: &[(&[u8; 2], &str)] = &[
+    (b"BC", "barcode"),
+    (b"CN", "sequencing_center"),
+    (b"DS", "description"),
+    (b"DT", "produced_at"),
+    (b"FO", "flow_order"),
+    (b"KS", "key_sequence"),
+    (b"LB", "library"),
+    (b"PG", "program"),
+    (b"PI", "predicted_insert_size"),
+    (b"PL", "platform"),
+    (b"PM", "platform_model"),
+    (b"PU", "platform_unit"),
+    (b"SM", "sample"),
+];
+
+/// Standard @PG tags, and the names we give them.
+const PG_TAGS: &[(&[u8; 2], &str)] = &[
+    (b"PN", "name"),
+    (b"CL", "command_line"),
+    (b"PP", "previous_program_id"),
+    (b"DS", "description"),
+    (b"VN", "version"),
+];
+
+/// Push the fields of a header record onto `record`. Every standard tag gets
+/// a column (nothing if absent), and any non-standard tags are collected
+/// under `other_fields`, keyed by the raw tag.
+fn push_header_fields<'a, K, V>(
+    call: &EvaluatedCall,
+    record: &mut Record,
+    fields: impl Iterator<Item = (&'a K, &'a V)>,
+    standard_tags: &[(&[u8; 2], &str)],
+) where
+    K: AsRef<[u8; 2]> + 'a,
+    V: AsRef<[u8]> + 'a,
+{
+    for (_, name) in standard_tags {
+        record.push(*name, Value::nothing(call.head));
+    }
+
+    let mut other_fields = Record::new();
+    for (tag, value) in fields {
+        let tag = tag.as_ref();
+        let value = String::from_utf8_lossy(value.as_ref()).to_string();
+
+        match standard_tags.iter().find(|(t, _)| *t == tag) {
+            // the predicted median insert size is the only numeric field.
+            Some((b"PI", name)) => {
+                let value = match value.parse::<i64>() {
+                    Ok(n) => Value::int(n, call.head),
+                    Err(_) => call.head.with_string(value),
+                };
+                record.insert(*name, value);
+            }
+            Some((_, name)) => {
+                record.insert(*name, call.head.with_string(value));
+            }
+            None => other_fields.push(String::from_utf8_lossy(tag), call.head.with_string(value)),
+        }
+    }
+
+    record.push("other_fields", Value::record(other_fields, call.head));
+}
+
 /// Parse a B/SAM header
 pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
     // @HD in SAM.
@@ -42,35 +137,26 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
     let default_map = Map::default();
     let header = header_op.unwrap_or(&default_map);
 
-    let header_nuon = Value::record(
-        record!(
+    let mut header_record = record!(
         "version" => call.head.with_string(header.version()),
-        // what's the default..?
-        // if it's no good, we can always map -> string
-        "sorting_order" => call.head.with_string("unknown"),
-        "grouping" => call.head.with_string("unknown"),
-        "sub_sort_order" => call.head.with_string("unknown")
-        ),
-        call.head,
     );
+    push_header_fields(
+        call,
+        &mut header_record,
+        header.other_fields().iter(),
+        HD_TAGS,
+    );
+    let header_nuon = Value::record(header_record, call.head);
 
     // @SQ.
     let reference_sequences = h.reference_sequences();
     let mut reference_sequences_record = Record::new();
     for (name, f) in reference_sequences.iter() {
-        let seq_record = record! {
+        let mut seq_record = record! {
             "sequence_name" => call.head.with_string(name),
             "sequence_length" => Value::int(usize::from(f.length()) as i64, call.head),
-            // TODO: These methods may not exist in the current API - will need to check later
-            // "alternate_locus" => call.head.with_string_or(f.alternative_locus(), "No alternative locus."),
-            // "alternate_names" => call.head.with_string_or(f.alternative_names(), "No alternative names."),
-            // "assembly_id" => call.head.with_string_or(f.assembly_id(), "No assembly ID."),
-            // "description" => call.head.with_string_or(f.description(), "No description"),
-            // "md5" => call.head.with_string_or(f.md5_checksum(), "No md5 checksum"),
-            // "species" => call.head.with_string_or(f.species(), "No species name"),
-            // "molecule_topology" => call.head.with_string_or(f.molecule_topology(), "No molecule topology"),
-            // "uri" => call.head.with_string_or(f.uri(), "No URI"),
         };
+        push_header_fields(call, &mut seq_record, f.other_fields().iter(), SQ_TAGS);
         reference_sequences_record.push(name.to_string(), Value::record(seq_record, call.head));
     }
     let reference_sequences_nuon = Value::record(reference_sequences_record, call.head);
@@ -78,31 +164,30 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
     // @RG
     let read_groups = h.read_groups();
     let mut read_groups_record = Record::new();
-    for (id, _f) in read_groups.iter() {
-        let value = Value::record(
-            record! {
-                "id" => call.head.with_string(id),
-                "barcode" => call.head.with_string("unknown"),
-                "sequencing_center" => call.head.with_string("unknown"),
-                "description" => call.head.with_string("unknown"),
-                "flow_order" => call.head.with_string("unknown"),
-                "key_sequence" => call.head.with_string("unknown"),
-                "library" => call.head.with_string("unknown"),
-                "program" => call.head.with_string("unknown"),
-                "platform" => call.head.with_string("unknown"),
-                "predicted_insert_size" => Value::int(0, call.head),
-                "platform_model" => call.head.with_string("unknown"),
-                "platform_unit" => call.head.with_string("unknown"),
-                "sample" => call.head.with_string("unknown"),
-            },
-            call.head,
+    for (id, f) in read_groups.iter() {
+        let mut read_group_record = record! {
+            "id" => call.head.with_string(id),
+        };
+        push_header_fields(
+            call,
+            &mut read_group_record,
+            f.other_fields().iter(),
+            RG_TAGS,
         );
-        read_groups_record.push(id.to_string(), value);
+        read_groups_record.push(id.to_string(), Value::record(read_group_record, call.head));
     }
     let read_groups_nuon = Value::record(read_groups_record, call.head);
 
-    // @PG - disabled due to API changes
-    let programs_record = Record::new();
+    // @PG, in the order they appear, so the PP chain can be followed.
+    let programs = h.programs();
+    let mut programs_record = Record::new();
+    for (id, f) in programs.as_ref().iter() {
+        let mut program_record = record! {
+            "id" => call.head.with_string(id),
+        };
+        push_header_fields(call, &mut program_record, f.other_fields().iter(), PG_TAGS);
+        programs_record.push(id.to_string(), Value::record(program_record, call.head));
+    }
     let programs_nuon = Value::record(programs_record, call.head);
 
     // @CO
@@ -124,155 +209,333 @@ pub fn parse_header(call: &EvaluatedCall, h: &sam::Header) -> Value {
     )
 }
 
+/// How the alignment record fields should be decoded.
+#[derive(Clone, Copy, Default)]
+pub struct RecordOptions {
+    /// Quality scores as a list of Phred ints, rather than Phred+33 text.
+    pub phred_ints: bool,
+    /// Auxiliary tags as SAM text, rather than a record.
+    pub raw_tags: bool,
+}
+
+impl RecordOptions {
+    /// Read the options from the flags passed to the command.
+    pub fn from_call(call: &EvaluatedCall) -> Result<Self, LabeledError> {
+        Ok(Self {
+            phred_ints: call.has_flag("phred-ints")?,
+            raw_tags: call.has_flag("raw-tags")?,
+        })
+    }
+}
+
+/// Turn an I/O error raised while decoding a record into a [`LabeledError`].
+fn record_error(e: io::Error) -> LabeledError {
+    LabeledError::new(format!("Record decoding failed. cause of failure: {}", e))
+}
+
+/// The SAM character of a CIGAR operation.
+fn cigar_op_char(kind: Kind) -> char {
+    match kind {
+        Kind::Match => 'M',
+        Kind::Insertion => 'I',
+        Kind::Deletion => 'D',
+        Kind::Skip => 'N',
+        Kind::SoftClip => 'S',
+        Kind::HardClip => 'H',
+        Kind::Pad => 'P',
+        Kind::SequenceMatch => '=',
+        Kind::SequenceMismatch => 'X',
+    }
+}
+
+/// Optional integer fields are nothing when missing.
+fn optional_int(call: &EvaluatedCall, n: Option<usize>) -> Value {
+    n.map(|n| Value::int(n as i64, call.head))
+        .unwrap_or(Value::nothing(call.head))
+}
+
+/// The contents of a B-array, with integers widened.
+enum ArrayValues {
+    Int(char, Vec<i64>),
+    Float(Vec<f32>),
+}
+
+/// Read out the values of a B-array along with its SAM subtype.
+fn array_values(array: DataArray) -> io::Result<ArrayValues> {
+    fn widen<'a, N: Into<i64>>(
+        values: Box<dyn Iterator<Item = io::Result<N>> + 'a>,
+    ) -> io::Result<Vec<i64>> {
+        values.map(|n| n.map(Into::into)).collect()
+    }
+
+    Ok(match array {
+        DataArray::Int8(values) => ArrayValues::Int('c', widen(values.iter())?),
+        DataArray::UInt8(values) => ArrayValues::Int('C', widen(values.iter())?),
+        DataArray::Int16(values) => ArrayValues::Int('s', widen(values.iter())?),
+        DataArray::UInt16(values) => ArrayValues::Int('S', widen(values.iter())?),
+        DataArray::Int32(values) => ArrayValues::Int('i', widen(values.iter())?),
+        DataArray::UInt32(values) => ArrayValues::Int('I', widen(values.iter())?),
+        DataArray::Float(values) => ArrayValues::Float(values.iter().collect::<io::Result<_>>()?),
+    })
+}
+
+/// A `{type, value}` record for the string types other than `Z`, so that
+/// `to sam` can write them back with their type.
+fn typed_string_to_nuon(call: &EvaluatedCall, ty: &str, value: impl ToString) -> Value {
+    Value::record(
+        record! {
+            "type" => call.head.with_string(ty),
+            "value" => call.head.with_string(value),
+        },
+        call.head,
+    )
+}
+
+/// Convert an auxiliary field value into nuon.
+///
+/// `A` and `H` fields become a `{type, value}` record, the other strings are
+/// plain strings.
+fn data_value_to_nuon(call: &EvaluatedCall, value: DataValue) -> io::Result<Value> {
+    let nuon = match value {
+        DataValue::Character(c) => typed_string_to_nuon(call, "A", c as char),
+        DataValue::Int8(n) => Value::int(n as i64, call.head),
+        DataValue::UInt8(n) => Value::int(n as i64, call.head),
+        DataValue::Int16(n) => Value::int(n as i64, call.head),
+        DataValue::UInt16(n) => Value::int(n as i64, call.head),
+        DataValue::Int32(n) => Value::int(n as i64, call.head),
+        DataValue::UInt32(n) => Value::int(n as i64, call.head),
+        DataValue::Float(n) => Value::float(n as f64, call.head),
+        DataValue::String(s) => call.head.with_string(String::from_utf8_lossy(s)),
+        DataValue::Hex(h) => typed_string_to_nuon(call, "H", String::from_utf8_lossy(h)),
+        DataValue::Array(array) => match array_values(array)? {
+            ArrayValues::Int(_, values) => Value::list(
+                values
+                    .into_iter()
+                    .map(|n| Value::int(n, call.head))
+                    .collect(),
+                call.head,
+            ),
+            ArrayValues::Float(values) => Value::list(
+                values
+                    .into_iter()
+                    .map(|n| Value::float(n as f64, call.head))
+                    .collect(),
+                call.head,
+            ),
+        },
+    };
+
+    Ok(nuon)
+}
+
+/// Format an auxiliary field value in its SAM TYPE:VALUE form.
+fn data_value_to_sam(value: DataValue) -> io::Result<String> {
+    let sam = match value {
+        DataValue::Character(c) => format!("A:{}", c as char),
+        DataValue::Int8(n) => format!("i:{}", n),
+        DataValue::UInt8(n) => format!("i:{}", n),
+        DataValue::Int16(n) => format!("i:{}", n),
+        DataValue::UInt16(n) => format!("i:{}", n),
+        DataValue::Int32(n) => format!("i:{}", n),
+        DataValue::UInt32(n) => format!("i:{}", n),
+        DataValue::Float(n) => format!("f:{}", n),
+        DataValue::String(s) => format!("Z:{}", String::from_utf8_lossy(s)),
+        DataValue::Hex(h) => format!("H:{}", String::from_utf8_lossy(h)),
+        DataValue::Array(array) => match array_values(array)? {
+            ArrayValues::Int(subtype, values) => values
+                .iter()
+                .fold(format!("B:{}", subtype), |a, n| format!("{},{}", a, n)),
+            ArrayValues::Float(values) => values
+                .iter()
+                .fold(String::from("B:f"), |a, n| format!("{},{}", a, n)),
+        },
+    };
+
+    Ok(sam)
+}
+
+/// Parse the auxiliary data of a record into a record keyed by tag, or into
+/// the tab separated SAM text if `raw` is set.
+fn data_to_nuon<'a>(
+    call: &EvaluatedCall,
+    fields: Box<dyn Iterator<Item = io::Result<(Tag, DataValue<'a>)>> + 'a>,
+    raw: bool,
+) -> io::Result<Value> {
+    if raw {
+        let sam = fields
+            .map(|field| {
+                let (tag, value) = field?;
+                let tag: &[u8; 2] = tag.as_ref();
+                Ok(format!(
+                    "{}:{}",
+                    String::from_utf8_lossy(tag),
+                    data_value_to_sam(value)?
+                ))
+            })
+            .collect::<io::Result<Vec<_>>>()?
+            .join("\t");
+
+        return Ok(call.head.with_string(sam));
+    }
+
+    let mut data = Record::new();
+    for field in fields {
+        let (tag, value) = field?;
+        let tag: &[u8; 2] = tag.as_ref();
+        data.push(
+            String::from_utf8_lossy(tag),
+            data_value_to_nuon(call, value)?,
+        );
+    }
+
+    Ok(Value::record(data, call.head))
+}
+
 /// Parse a SAM record, and append to a vector
 pub fn create_record_values<R: SAMRecord>(
     call: &EvaluatedCall,
     r: R,
     header: &sam::Header,
-) -> Vec<Value> {
-    // Extract basic fields that we know work
-    let flags = r.flags().map(|f| f.bits()).unwrap_or(0);
+    options: RecordOptions,
+) -> Result<Vec<Value>, LabeledError> {
+    let flags = r.flags().map_err(record_error)?.bits();
 
     let mapping_quality = r
         .mapping_quality()
-        .map(|m_q| {
-            m_q.map(|mq| format!("{}", u8::from(mq)))
-                .unwrap_or("*".to_string())
-        })
-        .unwrap_or("*".to_string());
-
-    let read_name = r
-        .name()
-        .map(|n| String::from_utf8_lossy(n).to_string())
-        .unwrap_or("*".to_string());
+        .transpose()
+        .map_err(record_error)?
+        .map(u8::from);
 
     let reference_sequence_id = r
         .reference_sequence_id(header)
-        .map(|id| {
-            id.map(|i| format!("{}", usize::from(i)))
-                .unwrap_or("*".to_string())
-        })
-        .unwrap_or("*".to_string());
+        .transpose()
+        .map_err(record_error)?;
 
     let alignment_start = r
         .alignment_start()
-        .map(|pos| {
-            pos.map(|p| format!("{}", usize::from(p)))
-                .unwrap_or("0".to_string())
-        })
-        .unwrap_or("0".to_string());
+        .transpose()
+        .map_err(record_error)?
+        .map(usize::from);
 
-    // Extract CIGAR, sequence, and quality scores - TODO fix, simplified for now
     let cigar = {
-        let c = r.cigar();
-        format!("cigar_ops:{}", c.len())
-    };
+        let ops = r
+            .cigar()
+            .iter()
+            .map(|op| op.map(|op| format!("{}{}", op.len(), cigar_op_char(op.kind()))))
+            .collect::<io::Result<String>>()
+            .map_err(record_error)?;
 
-    let sequence = {
-        let seq = r.sequence();
-        // TODO: Just show sequence length for now
-        if seq.len() > 0 {
-            format!("sequence_length:{}", seq.len())
-        } else {
+        if ops.is_empty() {
             "*".to_string()
+        } else {
+            ops
         }
     };
 
-    let quality_scores = {
-        let qual = r.quality_scores();
-        // TODO: Debug quality scores information
-        let len = qual.len();
-        if len > 0 {
-            format!("quality_length:{}", len)
+    let sequence = {
+        let bases: Vec<u8> = r.sequence().iter().collect();
+        if bases.is_empty() {
+            "*".to_string()
         } else {
-            // Show that we have quality scores object but it's empty
-            "quality_length:0".to_string()
+            String::from_utf8_lossy(&bases).to_string()
         }
     };
 
+    let quality_scores = r
+        .quality_scores()
+        .iter()
+        .collect::<io::Result<Vec<u8>>>()
+        .map_err(record_error)?;
+
+    let quality_scores_nuon = if options.phred_ints {
+        Value::list(
+            quality_scores
+                .iter()
+                .map(|q| Value::int(*q as i64, call.head))
+                .collect(),
+            call.head,
+        )
+    } else if quality_scores.is_empty() {
+        call.head.with_string("*")
+    } else {
+        // Phred+33
+        let phred: String = quality_scores
+            .iter()
+            .map(|q| q.saturating_add(33) as char)
+            .collect();
+        call.head.with_string(phred)
+    };
+
     let mate_reference_sequence_id = r
         .mate_reference_sequence_id(header)
-        .map(|id| {
-            id.map(|i| format!("{}", usize::from(i)))
-                .unwrap_or("*".to_string())
-        })
-        .unwrap_or("*".to_string());
+        .transpose()
+        .map_err(record_error)?;
 
     let mate_alignment_start = r
         .mate_alignment_start()
-        .map(|pos| {
-            pos.map(|p| format!("{}", usize::from(p)))
-                .unwrap_or("0".to_string())
-        })
-        .unwrap_or("0".to_string());
+        .transpose()
+        .map_err(record_error)?
+        .map(usize::from);
 
-    let template_length = r
-        .template_length()
-        .map(|len| format!("{}", len))
-        .unwrap_or("0".to_string());
+    let template_length = r.template_length().map_err(record_error)?;
 
-    let data = r
-        .data()
-        .iter()
-        .filter_map(|field_result| {
-            field_result.ok().map(|(tag, value)| {
-                let tag_str = format!("{:?}", tag)
-                    .replace("Tag(\"", "")
-                    .replace("\")", "");
-                let value_str = match value {
-                    sam::alignment::record::data::field::Value::Character(c) => {
-                        format!("A:{}", c as char)
-                    }
-                    sam::alignment::record::data::field::Value::Int32(i) => format!("i:{}", i),
-                    sam::alignment::record::data::field::Value::Float(f) => format!("f:{}", f),
-                    sam::alignment::record::data::field::Value::String(s) => {
-                        format!("Z:{}", String::from_utf8_lossy(s))
-                    }
-                    sam::alignment::record::data::field::Value::Hex(h) => {
-                        format!("H:{}", String::from_utf8_lossy(h))
-                    }
-                    sam::alignment::record::data::field::Value::Array(_arr) => {
-                        "B:array".to_string()
-                    }
-                    _ => format!("{:?}", value),
-                };
-                format!("{}:{}", tag_str, value_str)
-            })
-        })
-        .collect::<Vec<_>>()
-        .join("\t");
+    let data = data_to_nuon(call, r.data().iter(), options.raw_tags).map_err(record_error)?;
 
-    vec![
-        call.head.with_string_or(r.name(), "No read name."),
+    Ok(vec![
+        call.head.with_string_or(r.name(), "*"),
         call.head.with_string(format!("{:#06x}", flags)),
-        call.head.with_string(reference_sequence_id),
-        call.head.with_string(alignment_start),
-        call.head.with_string(mapping_quality),
+        optional_int(call, reference_sequence_id),
+        optional_int(call, alignment_start),
+        optional_int(call, mapping_quality.map(usize::from)),
         call.head.with_string(cigar),
-        call.head.with_string(mate_reference_sequence_id),
-        call.head.with_string(mate_alignment_start),
-        call.head.with_string(template_length),
+        optional_int(call, mate_reference_sequence_id),
+        optional_int(call, mate_alignment_start),
+        Value::int(template_length as i64, call.head),
         call.head.with_string(sequence),
-        call.head.with_string(quality_scores),
-        call.head.with_string(data),
-    ]
+        quality_scores_nuon,
+        data,
+    ])
 }
 
-/// Parse a BAM file into a nushell structure.
-pub fn from_bam_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val.clone(),
-        Value::String { val, .. } => val.as_bytes().to_vec(),
-        other => {
-            return Err(LabeledError::new(format!(
-                "Input must be binary or string data, got {}",
-                other.get_type()
-            )))
+/// Send every record of a B/SAM reader to the sink as a row of [`BAM_COLUMNS`].
+pub fn iterate_records<R, I>(
+    records: I,
+    call: &EvaluatedCall,
+    header: &sam::Header,
+    options: RecordOptions,
+    sink: Sink,
+) -> Result<(), LabeledError>
+where
+    R: SAMRecord,
+    I: Iterator<Item = io::Result<R>>,
+{
+    for record in records {
+        let r = record.map_err(|e| {
+            LabeledError::new(format!("Record reading failed. cause of failure: {}", e))
+        })?;
+
+        let inner_record = Record::from_iter(
+            BAM_COLUMNS
+                .iter()
+                .map(|e| e.to_string())
+                .zip(create_record_values(call, r, header, options)?),
+        );
+
+        if !sink(Value::record(inner_record, call.head)) {
+            break;
         }
-    };
+    }
+
+    Ok(())
+}
 
-    let mut reader = bam::io::Reader::new(stream.as_slice());
+/// Parse a BAM file into a nushell structure.
+pub fn from_bam_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    input: PipelineData,
+) -> Result<PipelineData, LabeledError> {
+    let mut reader = bam::io::Reader::new(input_reader(input, call.head)?);
     let raw_header = reader.read_header().map_err(|err| {
         LabeledError::new(format!(
             "Could not read header. error reading header at {}",
@@ -295,71 +558,659 @@ pub fn from_bam_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, Labe
         parse_header(call, &raw_header)
     };
 
-    let value_records = reader
-        .records()
-        .map(|record| {
-            let r = record.map_err(|e| {
-                LabeledError::new(format!("Record reading failed. cause of failure: {}", e))
-            })?;
+    let options = RecordOptions::from_call(call)?;
+    let stream = call.has_flag("stream")?;
+    let call = call.clone();
 
-            let inner_record = Record::from_iter(
-                BAM_COLUMNS
-                    .iter()
-                    .map(|e| e.to_string())
-                    .zip(create_record_values(call, r, &raw_header)),
-            );
+    header_body_output(call.head, signals, stream, header, move |sink| {
+        iterate_records(reader.records(), &call, &raw_header, options, sink)
+    })
+}
 
-            Ok(Value::record(inner_record, call.head))
-        })
-        .collect::<Result<Vec<_>, LabeledError>>()?;
+/// Query a coordinate sorted BAM for the alignments overlapping `region`,
+/// using its `.bai` (or `.csi`) index to seek straight to them.
+pub fn query_bam_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    path: PathBuf,
+    region: &str,
+) -> Result<ListStream, LabeledError> {
+    let region = parse_region(region)?;
+    let index_path = find_index(&path, &["bai", "csi"])?;
 
-    Ok(Value::record(
-        record! {
-            "header" => header,
-            "body" => Value::list(value_records, call.head)
-        },
-        call.head,
-    ))
+    let options = RecordOptions::from_call(call)?;
+    let call = call.clone();
+
+    // the indexed reader isn't Send, so it's opened on the streaming thread.
+    Ok(stream_values(call.head, signals, move |sink| {
+        let index_error = |e: io::Error| {
+            LabeledError::new(format!(
+                "Could not read the index {}. cause of failure: {}",
+                index_path.display(),
+                e
+            ))
+        };
+
+        let builder = bam::io::indexed_reader::Builder::default();
+        let builder = match index_path.extension().and_then(|e| e.to_str()) {
+            Some("csi") => builder.set_index(csi::fs::read(&index_path).map_err(index_error)?),
+            _ => builder.set_index(bam::bai::fs::read(&index_path).map_err(index_error)?),
+        };
+
+        let mut reader = builder.build_from_path(&path).map_err(|e| {
+            LabeledError::new(format!(
+                "Could not open {}. cause of failure: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        let header = reader.read_header().map_err(|err| {
+            LabeledError::new(format!(
+                "Could not read header. error reading header at {}",
+                err
+            ))
+        })?;
+
+        let query = reader.query(&header, &region).map_err(|e| {
+            LabeledError::new(format!(
+                "Could not query region {}. cause of failure: {}",
+                region, e
+            ))
+        })?;
+
+        iterate_records(query, &call, &header, options, sink)
+    }))
 }
 
 /// Parse a SAM file into a nushell structure.
-pub fn from_sam_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val.clone(),
-        Value::String { val, .. } => val.as_bytes().to_vec(),
-        _ => return Err(LabeledError::new("Input must be binary or string data")),
-    };
-
-    let mut reader = sam::io::Reader::new(BufReader::new(Cursor::new(stream)));
+pub fn from_sam_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    input: PipelineData,
+) -> Result<PipelineData, LabeledError> {
+    let mut reader = sam::io::Reader::new(BufReader::new(input_reader(input, call.head)?));
     let header = reader
         .read_header()
         .map_err(|err| LabeledError::new(format!("Unable to parse SAM header: {}", err)))?;
     let header_nuon = parse_header(call, &header);
 
-    let value_records = reader
-        .records()
-        .map(|record| {
-            let r = record.map_err(|e| {
-                LabeledError::new(format!("Record reading failed. cause of failure: {}", e))
-            })?;
+    let options = RecordOptions::from_call(call)?;
+    let stream = call.has_flag("stream")?;
+    let call = call.clone();
+
+    header_body_output(call.head, signals, stream, header_nuon, move |sink| {
+        iterate_records(reader.records(), &call, &header, options, sink)
+    })
+}
+
+/// Get a column of a row, or say which row is missing it.
+fn row_field<'a>(row: &'a Record, column: &str, i: usize) -> Result<&'a Value, LabeledError> {
+    row.get(column)
+        .ok_or_else(|| LabeledError::new(format!("Row {i} is missing the {column} column")))
+}
 
-            let inner_record = Record::from_iter(
-                BAM_COLUMNS
+/// Read an int column, where nothing means the field is missing.
+fn optional_int_field(row: &Record, column: &str, i: usize) -> Result<Option<i64>, LabeledError> {
+    match row_field(row, column, i)? {
+        Value::Nothing { .. } => Ok(None),
+        other => other
+            .as_int()
+            .map(Some)
+            .map_err(|e| LabeledError::new(format!("Row {i} has a malformed {column}: {e}"))),
+    }
+}
+
+/// The text of a header value, skipping nothing.
+fn header_value_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Nothing { .. } => None,
+        Value::String { val, .. } => Some(val.clone()),
+        Value::Int { val, .. } => Some(val.to_string()),
+        Value::Float { val, .. } => Some(val.to_string()),
+        other => other.coerce_string().ok(),
+    }
+}
+
+/// Build a header line (e.g. `@RG\tID:x\tSM:y`) from the leading fields, the
+/// standard tags and any other fields in a parsed header record.
+fn header_line(
+    kind: &str,
+    leading: &[(&str, String)],
+    record: &Record,
+    standard_tags: &[(&[u8; 2], &str)],
+) -> String {
+    let mut line = String::from(kind);
+
+    for (tag, value) in leading {
+        line.push_str(&format!("\t{tag}:{value}"));
+    }
+
+    for (tag, name) in standard_tags {
+        if let Some(value) = record.get(*name).and_then(header_value_text) {
+            line.push_str(&format!("\t{}:{}", String::from_utf8_lossy(*tag), value));
+        }
+    }
+
+    if let Some(Value::Record { val, .. }) = record.get("other_fields") {
+        for (tag, value) in val.iter() {
+            if let Some(value) = header_value_text(value) {
+                line.push_str(&format!("\t{tag}:{value}"));
+            }
+        }
+    }
+
+    line.push('\n');
+    line
+}
+
+/// Rebuild the SAM header text from the header record made by [`parse_header`].
+///
+/// Also returns the reference sequence names, so ids in the body can be
+/// mapped back to names.
+fn header_text_from_nuon(header: &Record) -> Result<(String, Vec<String>), LabeledError> {
+    let mut text = String::new();
+    let mut reference_names = Vec::new();
+
+    let section = |name: &str| match header.get(name) {
+        Some(Value::Record { val, .. }) => Ok(Some(val.clone().into_owned())),
+        Some(Value::Nothing { .. }) | None => Ok(None),
+        Some(other) => Err(LabeledError::new(format!(
+            "Header {name} should be a record, got {}",
+            other.get_type()
+        ))),
+    };
+
+    // @HD
+    if let Some(metadata) = section(HEADER_COLUMNS[0])? {
+        if let Some(version) = metadata.get("version").and_then(header_value_text) {
+            text.push_str(&header_line("@HD", &[("VN", version)], &metadata, HD_TAGS));
+        }
+    }
+
+    // @SQ
+    if let Some(reference_sequences) = section(HEADER_COLUMNS[1])? {
+        for (name, value) in reference_sequences.iter() {
+            let sq = value.as_record()?;
+            let length = sq
+                .get("sequence_length")
+                .and_then(header_value_text)
+                .ok_or_else(|| {
+                    LabeledError::new(format!("Reference sequence {name} has no length"))
+                })?;
+
+            text.push_str(&header_line(
+                "@SQ",
+                &[("SN", name.clone()), ("LN", length)],
+                sq,
+                SQ_TAGS,
+            ));
+            reference_names.push(name.clone());
+        }
+    }
+
+    // @RG
+    if let Some(read_groups) = section(HEADER_COLUMNS[2])? {
+        for (id, value) in read_groups.iter() {
+            text.push_str(&header_line(
+                "@RG",
+                &[("ID", id.clone())],
+                value.as_record()?,
+                RG_TAGS,
+            ));
+        }
+    }
+
+    // @PG
+    if let Some(programs) = section(HEADER_COLUMNS[3])? {
+        for (id, value) in programs.iter() {
+            text.push_str(&header_line(
+                "@PG",
+                &[("ID", id.clone())],
+                value.as_record()?,
+                PG_TAGS,
+            ));
+        }
+    }
+
+    // @CO
+    if let Some(Value::List { vals, .. }) = header.get(HEADER_COLUMNS[4]) {
+        for comment in vals {
+            text.push_str(&format!("@CO\t{}\n", comment.as_str()?));
+        }
+    }
+
+    Ok((text, reference_names))
+}
+
+/// The smallest B-array subtype that holds all the values.
+fn int_array_subtype(values: &[i64]) -> char {
+    let min = values.iter().copied().min().unwrap_or(0);
+    let max = values.iter().copied().max().unwrap_or(0);
+
+    if min >= 0 {
+        match max {
+            m if m <= u8::MAX as i64 => 'C',
+            m if m <= u16::MAX as i64 => 'S',
+            _ => 'I',
+        }
+    } else if min >= i8::MIN as i64 && max <= i8::MAX as i64 {
+        'c'
+    } else if min >= i16::MIN as i64 && max <= i16::MAX as i64 {
+        's'
+    } else {
+        'i'
+    }
+}
+
+/// Format one auxiliary field from the typed `data` record as TAG:TYPE:VALUE.
+///
+/// Strings are written as `Z`, and the `{type, value}` records of `A` and `H`
+/// fields with their own type.
+fn data_field_to_sam(tag: &str, value: &Value, i: usize) -> Result<String, LabeledError> {
+    let type_value = match value {
+        Value::Int { val, .. } => format!("i:{val}"),
+        Value::Float { val, .. } => format!("f:{val}"),
+        Value::String { val, .. } => format!("Z:{val}"),
+        Value::Record { val, .. } => {
+            let get = |key: &str| match val.get(key) {
+                Some(v) => v.coerce_string().map_err(LabeledError::from),
+                None => Err(LabeledError::new(format!(
+                    "Row {i} has no {key} for tag {tag}"
+                ))),
+            };
+            format!("{}:{}", get("type")?, get("value")?)
+        }
+        Value::List { vals, .. } => {
+            if vals.iter().all(|v| matches!(v, Value::Int { .. })) {
+                let ints = vals
+                    .iter()
+                    .map(|v| v.as_int().map_err(LabeledError::from))
+                    .collect::<Result<Vec<_>, _>>()?;
+                ints.iter()
+                    .fold(format!("B:{}", int_array_subtype(&ints)), |a, n| {
+                        format!("{a},{n}")
+                    })
+            } else {
+                let floats = vals
+                    .iter()
+                    .map(|v| v.as_float().map_err(LabeledError::from))
+                    .collect::<Result<Vec<_>, _>>()?;
+                floats
                     .iter()
-                    .map(|e| e.to_string())
-                    .zip(create_record_values(call, r, &header)),
-            );
+                    .fold(String::from("B:f"), |a, n| format!("{a},{n}"))
+            }
+        }
+        other => {
+            return Err(LabeledError::new(format!(
+                "Row {i} has an unsupported value for tag {tag}: {}",
+                other.get_type()
+            )))
+        }
+    };
+
+    Ok(format!("{tag}:{type_value}"))
+}
+
+/// Format a row of [`BAM_COLUMNS`] as a SAM line.
+fn record_line_from_nuon(
+    row: &Record,
+    reference_names: &[String],
+    i: usize,
+) -> Result<String, LabeledError> {
+    let reference_name = |column: &str| -> Result<String, LabeledError> {
+        match optional_int_field(row, column, i)? {
+            None => Ok("*".into()),
+            Some(id) => reference_names.get(id as usize).cloned().ok_or_else(|| {
+                LabeledError::new(format!(
+                    "Row {i} has {column} {id}, which is not in the header"
+                ))
+            }),
+        }
+    };
+
+    let name = row_field(row, "read_name", i)?.as_str()?.to_string();
 
-            Ok(Value::record(inner_record, call.head))
+    let flags = match row_field(row, "flags", i)? {
+        Value::String { val, .. } => u16::from_str_radix(val.trim_start_matches("0x"), 16)
+            .map_err(|e| LabeledError::new(format!("Row {i} has malformed flags {val}: {e}")))?,
+        other => other.as_int()? as u16,
+    };
+
+    let quality_scores = match row_field(row, "quality_scores", i)? {
+        Value::List { vals, .. } if vals.is_empty() => "*".to_string(),
+        Value::List { vals, .. } => vals
+            .iter()
+            .map(|q| {
+                q.as_int()
+                    .map(|q| (q as u8).saturating_add(33) as char)
+                    .map_err(LabeledError::from)
+            })
+            .collect::<Result<String, _>>()?,
+        other => other.as_str()?.to_string(),
+    };
+
+    let data = match row_field(row, "data", i)? {
+        Value::Record { val, .. } => val
+            .iter()
+            .map(|(tag, value)| data_field_to_sam(tag, value, i))
+            .collect::<Result<Vec<_>, _>>()?,
+        Value::String { val, .. } if !val.is_empty() => vec![val.clone()],
+        _ => Vec::new(),
+    };
+
+    let mut fields = vec![
+        name,
+        flags.to_string(),
+        reference_name("reference_sequence_id")?,
+        optional_int_field(row, "alignment_start", i)?
+            .unwrap_or(0)
+            .to_string(),
+        optional_int_field(row, "mapping_quality", i)?
+            .unwrap_or(255)
+            .to_string(),
+        row_field(row, "cigar", i)?.as_str()?.to_string(),
+        reference_name("mate_reference_sequence_id")?,
+        optional_int_field(row, "mate_alignment_start", i)?
+            .unwrap_or(0)
+            .to_string(),
+        optional_int_field(row, "template_length", i)?
+            .unwrap_or(0)
+            .to_string(),
+        row_field(row, "sequence", i)?.as_str()?.to_string(),
+        quality_scores,
+    ];
+    fields.extend(data);
+
+    Ok(fields.join("\t") + "\n")
+}
+
+/// Rebuild the SAM text from the `{header, body}` record made by `from sam`
+/// or `from bam`.
+fn sam_text_from_nuon(input: &Value) -> Result<String, LabeledError> {
+    let input = input.as_record()?;
+
+    let header = match input.get("header") {
+        Some(h) => h.as_record()?,
+        None => return Err(LabeledError::new(
+            "Input has no header: expected the {header, body} record from `from sam` or `from bam`",
+        )),
+    };
+    let body = match input.get("body") {
+        Some(b) => b.as_list()?,
+        None => return Err(LabeledError::new(
+            "Input has no body: expected the {header, body} record from `from sam` or `from bam`",
+        )),
+    };
+
+    let (mut text, reference_names) = header_text_from_nuon(header)?;
+
+    for (i, row) in body.iter().enumerate() {
+        text.push_str(&record_line_from_nuon(
+            row.as_record()?,
+            &reference_names,
+            i,
+        )?);
+    }
+
+    Ok(text)
+}
+
+/// Read the rebuilt SAM text back into a header and records, so they are
+/// validated by noodles before being written out.
+fn read_alignments(text: &str) -> Result<(sam::Header, Vec<sam::Record>), LabeledError> {
+    let mut reader = sam::io::Reader::new(text.as_bytes());
+    let header = reader
+        .read_header()
+        .map_err(|e| LabeledError::new(format!("Could not rebuild the SAM header: {e}")))?;
+
+    let records = reader
+        .records()
+        .enumerate()
+        .map(|(i, result)| {
+            result.map_err(|e| LabeledError::new(format!("Could not rebuild row {i}: {e}")))
         })
-        .collect::<Result<Vec<_>, LabeledError>>()?;
+        .collect::<Result<Vec<_>, _>>()?;
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head)
-        },
-        call.head,
-    ))
+    Ok((header, records))
+}
+
+/// Go from the `{header, body}` record of `from sam`/`from bam` to SAM text.
+pub fn nuon_to_sam(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let (header, records) = read_alignments(&sam_text_from_nuon(input)?)?;
+
+    let mut writer = sam::io::Writer::new(Vec::new());
+    writer
+        .write_header(&header)
+        .map_err(|e| LabeledError::new(format!("Could not write the SAM header: {e}")))?;
+
+    for (i, record) in records.iter().enumerate() {
+        writer
+            .write_alignment_record(&header, record)
+            .map_err(|e| LabeledError::new(format!("Could not write row {i}: {e}")))?;
+    }
+
+    let out_final = String::from_utf8(writer.into_inner())
+        .map_err(|err| LabeledError::new(format!("Can't format bytes as UTF-8: {}", err)))?;
+
+    Ok(Value::string(out_final, call.head))
+}
+
+/// Go from the `{header, body}` record of `from sam`/`from bam` to BGZF
+/// compressed BAM.
+pub fn nuon_to_bam(
+    call: &EvaluatedCall,
+    input: &Value,
+    compression_level: Option<i64>,
+) -> Result<Value, LabeledError> {
+    let (header, records) = read_alignments(&sam_text_from_nuon(input)?)?;
+
+    let mut builder = bgzf::io::writer::Builder::default();
+    if let Some(level) = compression_level {
+        let level = u8::try_from(level)
+            .ok()
+            .and_then(bgzf::io::writer::CompressionLevel::new)
+            .ok_or_else(|| {
+                LabeledError::new(format!("Invalid compression level {level}, expected 0-9"))
+            })?;
+        builder = builder.set_compression_level(level);
+    }
+
+    let mut writer = bam::io::Writer::from(builder.build_from_writer(Vec::new()));
+    writer
+        .write_header(&header)
+        .map_err(|e| LabeledError::new(format!("Could not write the BAM header: {e}")))?;
+
+    for (i, record) in records.iter().enumerate() {
+        writer
+            .write_alignment_record(&header, record)
+            .map_err(|e| LabeledError::new(format!("Could not write row {i}: {e}")))?;
+    }
+
+    // writes the final block and the BGZF EOF marker.
+    let out = writer
+        .into_inner()
+        .finish()
+        .map_err(|e| LabeledError::new(format!("Could not finish the BAM: {e}")))?;
+
+    Ok(Value::binary(out, call.head))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bio_format::tests::{call_with_switches, fixture, test_call};
+    use nu_protocol::Span;
+
+    const SAM: &str = "@HD\tVN:1.6\tSO:unsorted
+@SQ\tSN:chr1\tLN:100
+r1\t0\tchr1\t1\t60\t4M\t*\t0\t0\tACGT\tIIII\tXA:A:c\tXH:H:1AE3\tXZ:Z:hello\tNM:i:1\tXB:B:c,1,-2
+";
+
+    fn from_sam(call: &EvaluatedCall, text: &str) -> Value {
+        let input = PipelineData::Value(Value::test_string(text), None);
+        from_sam_inner(call, &Signals::empty(), input)
+            .and_then(|output| Ok(output.into_value(Span::test_data())?))
+            .unwrap()
+    }
+
+    fn from_bam(bytes: Vec<u8>) -> Value {
+        let input = PipelineData::Value(Value::test_binary(bytes), None);
+        from_bam_inner(&test_call(), &Signals::empty(), input)
+            .and_then(|output| Ok(output.into_value(Span::test_data())?))
+            .unwrap()
+    }
+
+    fn body_line(sam: &str) -> &str {
+        sam.lines().find(|line| !line.starts_with('@')).unwrap()
+    }
+
+    #[test]
+    fn sam_round_trip_keeps_tag_types() {
+        for call in [test_call(), call_with_switches(&["raw-tags"])] {
+            let sam = nuon_to_sam(&call, &from_sam(&call, SAM)).unwrap();
+            assert_eq!(body_line(sam.as_str().unwrap()), body_line(SAM));
+        }
+    }
+
+    #[test]
+    fn character_and_hex_tags_keep_their_type() {
+        let sam = from_sam(&test_call(), SAM);
+        let data = sam.get_data_by_key("body").unwrap().as_list().unwrap()[0]
+            .get_data_by_key("data")
+            .unwrap();
+
+        let hex = data.get_data_by_key("XH").unwrap();
+        assert_eq!(hex.get_data_by_key("type").unwrap().as_str().unwrap(), "H");
+        assert_eq!(
+            hex.get_data_by_key("value").unwrap().as_str().unwrap(),
+            "1AE3"
+        );
+        assert_eq!(
+            data.get_data_by_key("XZ").unwrap().as_str().unwrap(),
+            "hello"
+        );
+    }
+
+    #[test]
+    fn bam_round_trip() {
+        let bam = from_bam(std::fs::read(fixture("map.bam")).unwrap());
+        let written = nuon_to_bam(&test_call(), &bam, Some(1)).unwrap();
+        let read_back = from_bam(written.as_binary().unwrap().to_vec());
+
+        let body = |v: &Value| v.get_data_by_key("body").unwrap();
+        assert!(!body(&bam).as_list().unwrap().is_empty());
+        assert_eq!(body(&read_back), body(&bam));
+        assert_eq!(
+            read_back.get_data_by_key("header").unwrap(),
+            bam.get_data_by_key("header").unwrap()
+        );
+    }
+
+    fn first_record(sam: &Value) -> Value {
+        sam.get_data_by_key("body").unwrap().as_list().unwrap()[0].clone()
+    }
+
+    #[test]
+    fn sequence_cigar_and_quality_are_decoded() {
+        let record = first_record(&from_sam(&test_call(), SAM));
+        let get = |key: &str| record.get_data_by_key(key).unwrap();
+
+        assert_eq!(get("sequence").as_str().unwrap(), "ACGT");
+        assert_eq!(get("cigar").as_str().unwrap(), "4M");
+        assert_eq!(get("quality_scores").as_str().unwrap(), "IIII");
+        assert_eq!(get("alignment_start").as_int().unwrap(), 1);
+
+        let record = first_record(&from_sam(&call_with_switches(&["phred-ints"]), SAM));
+        let scores: Vec<i64> = record
+            .get_data_by_key("quality_scores")
+            .unwrap()
+            .as_list()
+            .unwrap()
+            .iter()
+            .map(|q| q.as_int().unwrap())
+            .collect();
+        assert_eq!(scores, [40, 40, 40, 40]);
+    }
+
+    #[test]
+    fn tags_are_typed() {
+        let record = first_record(&from_sam(&test_call(), SAM));
+        let data = record.get_data_by_key("data").unwrap();
+        let tag = |key: &str| data.get_data_by_key(key).unwrap();
+
+        assert_eq!(tag("NM").as_int().unwrap(), 1);
+        assert_eq!(tag("XZ").as_str().unwrap(), "hello");
+        let array: Vec<i64> = tag("XB")
+            .as_list()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_int().unwrap())
+            .collect();
+        assert_eq!(array, [1, -2]);
+
+        let raw = first_record(&from_sam(&call_with_switches(&["raw-tags"]), SAM));
+        assert_eq!(
+            raw.get_data_by_key("data").unwrap().as_str().unwrap(),
+            "XA:A:c\tXH:H:1AE3\tXZ:Z:hello\tNM:i:1\tXB:B:c,1,-2"
+        );
+    }
+
+    #[test]
+    fn header_records_are_parsed() {
+        let text = "@HD\tVN:1.6\tSO:coordinate
+@SQ\tSN:chr1\tLN:100
+@RG\tID:rg1\tSM:sample1\tPI:300\tXX:custom
+@PG\tID:aligner\tPN:bwa\tVN:0.7
+@PG\tID:sorter\tPN:samtools\tPP:aligner
+@CO\tsome comment
+";
+        let header = from_sam(&test_call(), text)
+            .get_data_by_key("header")
+            .unwrap();
+        let field = |path: &[&str]| {
+            path.iter()
+                .fold(header.clone(), |v, key| v.get_data_by_key(key).unwrap())
+        };
+
+        assert_eq!(field(&["metadata", "version"]).as_str().unwrap(), "1.6");
+        assert_eq!(
+            field(&["metadata", "sorting_order"]).as_str().unwrap(),
+            "coordinate"
+        );
+        assert!(field(&["metadata", "grouping"]).is_nothing());
+
+        assert_eq!(
+            field(&["read_groups", "rg1", "sample"]).as_str().unwrap(),
+            "sample1"
+        );
+        assert_eq!(
+            field(&["read_groups", "rg1", "predicted_insert_size"])
+                .as_int()
+                .unwrap(),
+            300
+        );
+        assert_eq!(
+            field(&["read_groups", "rg1", "other_fields", "XX"])
+                .as_str()
+                .unwrap(),
+            "custom"
+        );
+
+        let programs = field(&["programs"]);
+        assert_eq!(
+            programs.as_record().unwrap().columns().collect::<Vec<_>>(),
+            ["aligner", "sorter"]
+        );
+        assert_eq!(
+            field(&["programs", "sorter", "previous_program_id"])
+                .as_str()
+                .unwrap(),
+            "aligner"
+        );
+        assert_eq!(
+            field(&["comments"]).as_list().unwrap()[0].as_str().unwrap(),
+            "some comment"
+        );
+    }
 }
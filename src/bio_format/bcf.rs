@@ -4,25 +4,36 @@ use noodles_bgzf as bgzf;
 use noodles_vcf as vcf;
 use nu_plugin::EvaluatedCall;
 use nu_protocol::LabeledError;
-use nu_protocol::{record, Record, Value};
+use nu_protocol::{record, ListStream, PipelineData, Record, Signals, Value};
+use vcf::header::record::value::Collection;
+use vcf::variant::io::Write as VariantWrite;
+use vcf::variant::record::info::field::{value::Array as InfoArray, Value as InfoValue};
+use vcf::variant::record::samples::series::{
+    value::{genotype::Phasing, Array as SampleArray, Genotype},
+    Value as SampleValue,
+};
+use vcf::variant::Record as VariantRecord;
 
-use crate::bio_format::Compression;
-use std::io::{BufRead, BufReader, Cursor};
-
-type StringMaps = vcf::header::StringMaps;
+use crate::bio_format::index::{query_tabix_lines, read_tabix_index, TabixFormat};
+use crate::bio_format::{
+    header_body_output, input_reader, parse_region, stream_values, Compression, InputReader, Sink,
+};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
 
 use super::SpanExt;
 
 /// Compression status of a VCF reader.
-enum VCFReader<'a> {
-    Uncompressed(Box<vcf::io::Reader<&'a [u8]>>),
-    Compressed(Box<vcf::io::Reader<BufReader<bgzf::io::Reader<&'a [u8]>>>>),
+enum VCFReader {
+    Uncompressed(Box<vcf::io::Reader<BufReader<InputReader>>>),
+    Compressed(Box<vcf::io::Reader<BufReader<bgzf::io::Reader<InputReader>>>>),
 }
 
 /// Compression status of a BCF reader.
-enum BCFReader<'a> {
-    Uncompressed(Box<bcf::io::Reader<bgzf::io::Reader<&'a [u8]>>>),
-    Compressed(Box<bcf::io::Reader<bgzf::io::Reader<bgzf::io::Reader<&'a [u8]>>>>),
+enum BCFReader {
+    Uncompressed(Box<bcf::io::Reader<bgzf::io::Reader<InputReader>>>),
+    Compressed(Box<bcf::io::Reader<bgzf::io::Reader<bgzf::io::Reader<InputReader>>>>),
 }
 
 /// VCF column headers
@@ -51,20 +62,65 @@ const HEADER_COLUMNS: &[&str] = &[
     // "meta",
     // "pedigree",
     "samples",
+    "other",
 ];
 
+/// The `##fileformat` value, e.g. `VCFv4.3`.
+fn file_format_text(file_format: vcf::header::FileFormat) -> String {
+    format!("VCFv{}.{}", file_format.major(), file_format.minor())
+}
+
+/// The `Number` of an INFO header line as it is written in the VCF.
+fn info_number_text(number: vcf::header::record::value::map::info::Number) -> String {
+    use vcf::header::record::value::map::info::Number;
+
+    match number {
+        Number::Count(n) => n.to_string(),
+        Number::AlternateBases => "A".into(),
+        Number::ReferenceAlternateBases => "R".into(),
+        Number::Samples => "G".into(),
+        Number::Unknown => ".".into(),
+    }
+}
+
+/// The `Number` of a FORMAT header line as it is written in the VCF.
+fn format_number_text(number: vcf::header::record::value::map::format::Number) -> String {
+    use vcf::header::record::value::map::format::Number;
+
+    match number {
+        Number::Count(n) => n.to_string(),
+        Number::AlternateBases => "A".into(),
+        Number::ReferenceAlternateBases => "R".into(),
+        Number::Samples => "G".into(),
+        Number::LocalAlternateBases => "LA".into(),
+        Number::LocalReferenceAlternateBases => "LR".into(),
+        Number::LocalSamples => "LG".into(),
+        Number::Ploidy => "P".into(),
+        Number::BaseModifications => "M".into(),
+        Number::Unknown => ".".into(),
+    }
+}
+
+/// A field of a structured meta line, quoted if it has to be.
+fn meta_field_text(field: &str) -> String {
+    match field.contains([',', ' ', '"']) {
+        true => quote_description(field),
+        false => field.to_string(),
+    }
+}
+
 /// This parses the header of a V/BCF
 fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
-    let file_format = call.head.with_string(format!("{:?}", h.file_format()));
+    let file_format = call.head.with_string(file_format_text(h.file_format()));
     let infos = h.infos();
 
     let infos_inner = Record::from_iter(infos.keys().map(|e| e.to_string()).zip(
         infos.values().map(|f| {
             Value::record(
                 record! {
-                "number" => call.head.with_string(format!("{:?}", f.number())),
-                "type" => call.head.with_string(format!("{:?}", f.ty())),
-                "description" => call.head.with_string(format!("{:?}", f.description())),
+                "number" => call.head.with_string(info_number_text(f.number())),
+                "type" => call.head.with_string(f.ty()),
+                "description" => call.head.with_string(f.description()),
                 },
                 call.head,
             )
@@ -81,7 +137,7 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
         filters.values().map(|f| {
             Value::record(
                 record! {
-                      "description" => call.head.with_string(format!("{:?}", f.description()))
+                      "description" => call.head.with_string(f.description())
 
                 },
                 call.head,
@@ -98,9 +154,9 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
         formats.values().map(|f| {
             Value::record(
                 record! {
-                    "number" => call.head.with_string(format!("{:?}", f.number())),
-                    "type" => call.head.with_string(format!("{:?}", f.ty())),
-                    "description" => call.head.with_string(format!("{:?}", f.description()))
+                    "number" => call.head.with_string(format_number_text(f.number())),
+                    "type" => call.head.with_string(f.ty()),
+                    "description" => call.head.with_string(f.description())
                 },
                 call.head,
             )
@@ -116,7 +172,7 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
         alt_alleles.values().map(|f| {
             Value::record(
                 record! {
-                    "description" => call.head.with_string(format!("{:?}", f.description()))
+                    "description" => call.head.with_string(f.description())
                 },
                 call.head,
             )
@@ -133,11 +189,14 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
             let mut cols = vec!["length".into()];
             cols.extend(f.other_fields().keys().map(|e| e.to_string()));
 
-            let mut vals = vec![Value::int(f.length().unwrap_or(0) as i64, call.head)];
+            let mut vals = vec![optional_value(
+                call,
+                f.length().map(|n| Value::int(n as i64, call.head)),
+            )];
 
             vals.extend(f.other_fields().values().map(|e| call.head.with_string(e)));
 
-            let contig_vals_inner = Record::from_iter(cols.into_iter().zip(vals.into_iter()));
+            let contig_vals_inner = Record::from_iter(cols.into_iter().zip(vals));
 
             Value::record(contig_vals_inner, call.head)
         }),
@@ -156,7 +215,29 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
         call.head,
     );
 
-    // TODO: I've skipped other records for the moment.
+    // any other meta lines (e.g. ##reference), as their text after `key=`.
+    let other_inner = Record::from_iter(h.other_records().iter().map(|(key, collection)| {
+        let values = match collection {
+            Collection::Unstructured(values) => {
+                values.iter().map(|e| call.head.with_string(e)).collect()
+            }
+            Collection::Structured(maps) => maps
+                .iter()
+                .map(|(id, map)| {
+                    let mut text = format!("<ID={id}");
+                    for (key, field) in map.other_fields() {
+                        text.push_str(&format!(",{key}={}", meta_field_text(field)));
+                    }
+                    text.push('>');
+                    call.head.with_string(text)
+                })
+                .collect(),
+        };
+        (key.to_string(), Value::list(values, call.head))
+    }));
+
+    let other_nuon = Value::record(other_inner, call.head);
+
     // return the big record
     Value::record(
         Record::from_iter(HEADER_COLUMNS.iter().map(|e| e.to_string()).zip(vec![
@@ -167,53 +248,288 @@ fn parse_header(call: &EvaluatedCall, h: &vcf::Header) -> Value {
             alt_alleles_nuon,
             contigs_nuon,
             sample_names_nuon,
+            other_nuon,
         ])),
         call.head,
     )
 }
 
+/// Turn an I/O error raised while decoding a record into a [`LabeledError`].
+fn record_error(e: io::Error) -> LabeledError {
+    LabeledError::new(format!("Record decoding failed. cause of failure: {}", e))
+}
+
+/// Convert a single INFO field value into nuon.
+fn info_value_to_nuon(call: &EvaluatedCall, value: Option<InfoValue>) -> io::Result<Value> {
+    let value = match value {
+        Some(v) => v,
+        // a key with no value at all
+        None => return Ok(Value::nothing(call.head)),
+    };
+
+    let nuon = match value {
+        InfoValue::Integer(n) => Value::int(n as i64, call.head),
+        InfoValue::Float(n) => Value::float(n as f64, call.head),
+        InfoValue::Flag => Value::bool(true, call.head),
+        InfoValue::Character(c) => call.head.with_string(c),
+        InfoValue::String(s) => call.head.with_string(s),
+        InfoValue::Array(array) => {
+            let values = match array {
+                InfoArray::Integer(values) => values
+                    .iter()
+                    .map(|v| {
+                        v.map(|n| optional_value(call, n.map(|n| Value::int(n as i64, call.head))))
+                    })
+                    .collect::<io::Result<Vec<_>>>()?,
+                InfoArray::Float(values) => values
+                    .iter()
+                    .map(|v| {
+                        v.map(|n| {
+                            optional_value(call, n.map(|n| Value::float(n as f64, call.head)))
+                        })
+                    })
+                    .collect::<io::Result<Vec<_>>>()?,
+                InfoArray::Character(values) => values
+                    .iter()
+                    .map(|v| v.map(|c| optional_value(call, c.map(|c| call.head.with_string(c)))))
+                    .collect::<io::Result<Vec<_>>>()?,
+                InfoArray::String(values) => values
+                    .iter()
+                    .map(|v| v.map(|s| optional_value(call, s.map(|s| call.head.with_string(s)))))
+                    .collect::<io::Result<Vec<_>>>()?,
+            };
+            Value::list(values, call.head)
+        }
+    };
+
+    Ok(nuon)
+}
+
+/// Missing values (`.`) inside arrays become nothing.
+fn optional_value(call: &EvaluatedCall, v: Option<Value>) -> Value {
+    v.unwrap_or(Value::nothing(call.head))
+}
+
+/// Convert a genotype (GT) into a record of the allele indices and whether the
+/// genotype is phased. Missing alleles (`.`) become nothing.
+fn genotype_to_nuon(call: &EvaluatedCall, genotype: &dyn Genotype) -> io::Result<Value> {
+    let alleles = genotype.iter().collect::<io::Result<Vec<_>>>()?;
+
+    // the phasing of the first allele is implicit, unless it's haploid.
+    let phased = match alleles.split_first() {
+        Some((first, [])) => matches!(first.1, Phasing::Phased),
+        Some((_, rest)) => rest.iter().all(|(_, p)| matches!(p, Phasing::Phased)),
+        None => false,
+    };
+
+    let alleles_nuon = alleles
+        .iter()
+        .map(|(position, _)| {
+            optional_value(call, position.map(|i| Value::int(i as i64, call.head)))
+        })
+        .collect();
+
+    Ok(Value::record(
+        record! {
+            "alleles" => Value::list(alleles_nuon, call.head),
+            "phased" => Value::bool(phased, call.head),
+        },
+        call.head,
+    ))
+}
+
+/// Convert a single FORMAT field value of a sample into nuon.
+fn sample_value_to_nuon(call: &EvaluatedCall, value: Option<SampleValue>) -> io::Result<Value> {
+    let value = match value {
+        Some(v) => v,
+        None => return Ok(Value::nothing(call.head)),
+    };
+
+    let nuon = match value {
+        SampleValue::Integer(n) => Value::int(n as i64, call.head),
+        SampleValue::Float(n) => Value::float(n as f64, call.head),
+        SampleValue::Character(c) => call.head.with_string(c),
+        SampleValue::String(s) => call.head.with_string(s),
+        SampleValue::Genotype(genotype) => genotype_to_nuon(call, genotype.as_ref())?,
+        SampleValue::Array(array) => {
+            let values = match array {
+                SampleArray::Integer(values) => values
+                    .iter()
+                    .map(|v| {
+                        v.map(|n| optional_value(call, n.map(|n| Value::int(n as i64, call.head))))
+                    })
+                    .collect::<io::Result<Vec<_>>>()?,
+                SampleArray::Float(values) => values
+                    .iter()
+                    .map(|v| {
+                        v.map(|n| {
+                            optional_value(call, n.map(|n| Value::float(n as f64, call.head)))
+                        })
+                    })
+                    .collect::<io::Result<Vec<_>>>()?,
+                SampleArray::Character(values) => values
+                    .iter()
+                    .map(|v| v.map(|c| optional_value(call, c.map(|c| call.head.with_string(c)))))
+                    .collect::<io::Result<Vec<_>>>()?,
+                SampleArray::String(values) => values
+                    .iter()
+                    .map(|v| v.map(|s| optional_value(call, s.map(|s| call.head.with_string(s)))))
+                    .collect::<io::Result<Vec<_>>>()?,
+            };
+            Value::list(values, call.head)
+        }
+    };
+
+    Ok(nuon)
+}
+
+/// Decode the FORMAT fields of every sample into a record keyed by sample name.
+fn genotypes_to_nuon<R: VariantRecord>(
+    call: &EvaluatedCall,
+    header: &vcf::Header,
+    r: &R,
+) -> io::Result<Value> {
+    let samples = r.samples()?;
+    let mut genotypes = Record::new();
+
+    for (name, sample) in header.sample_names().iter().zip(samples.iter()) {
+        let mut fields = Record::new();
+        for field in sample.iter(header) {
+            let (key, value) = field?;
+            fields.push(key, sample_value_to_nuon(call, value)?);
+        }
+        genotypes.push(name, Value::record(fields, call.head));
+    }
+
+    Ok(Value::record(genotypes, call.head))
+}
+
+/// Split a variant row into one row per sample (`--long`). The `genotypes`
+/// column is replaced by a `sample` column and the sample's FORMAT fields.
+fn long_rows(call: &EvaluatedCall, mut row: Record) -> Vec<Value> {
+    let genotypes = match row.remove("genotypes") {
+        Some(Value::Record { val, .. }) => val.into_owned(),
+        _ => Record::new(),
+    };
+
+    genotypes
+        .into_iter()
+        .map(|(sample, fields)| {
+            let mut long_row = row.clone();
+            long_row.push("sample", call.head.with_string(sample));
+            if let Value::Record { val, .. } = fields {
+                for (key, value) in val.into_owned() {
+                    long_row.push(key, value);
+                }
+            }
+            Value::record(long_row, call.head)
+        })
+        .collect()
+}
+
+/// Send a finished variant row, expanding it per sample if `--long` was passed.
+/// Returns `false` once the sink is no longer listening.
+fn send_variant_row(call: &EvaluatedCall, row: Record, long: bool, sink: Sink) -> bool {
+    if long {
+        long_rows(call, row).into_iter().all(sink)
+    } else {
+        sink(Value::record(row, call.head))
+    }
+}
+
 /// Add a VCF record to the vector.
-/// TODO: make data more structured, so less is turned into a string immediately.
-fn add_record(call: &EvaluatedCall, r: vcf::Record, vec_vals: &mut Vec<Value>) {
-    // VCF API has changed significantly - using placeholder values
+///
+/// This is generic over [`VariantRecord`], so both VCF and BCF records are
+/// decoded through the same path against the parsed header.
+fn add_record<R: VariantRecord>(
+    call: &EvaluatedCall,
+    header: &vcf::Header,
+    r: &R,
+    vec_vals: &mut Vec<Value>,
+) -> io::Result<()> {
+    let chrom = call.head.with_string(r.reference_sequence_name(header)?);
+
+    let pos = r
+        .variant_start()
+        .transpose()?
+        .map(|p| usize::from(p) as i64)
+        // position 0 is a telomere.
+        .unwrap_or(0);
+
+    let rlen = r.variant_span(header)? as i64;
+
+    let qual = match r.quality_score().transpose()? {
+        Some(q) => Value::float(q as f64, call.head),
+        None => Value::nothing(call.head),
+    };
+
+    let ids = r.ids().iter().map(|id| call.head.with_string(id)).collect();
+
+    let reference_bases = r
+        .reference_bases()
+        .iter()
+        .collect::<io::Result<Vec<u8>>>()?;
+
+    let alternate_bases = r
+        .alternate_bases()
+        .iter()
+        .map(|alt| alt.map(|a| call.head.with_string(a)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let filters = r
+        .filters()
+        .iter(header)
+        .map(|f| f.map(|f| call.head.with_string(f)))
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let mut info = Record::new();
+    for field in r.info().iter(header) {
+        let (key, value) = field?;
+        info.push(key, info_value_to_nuon(call, value)?);
+    }
+
     let values_to_extend: Vec<Value> = vec![
-        call.head.with_string("unknown_chromosome"),
-        Value::int(0, call.head),
-        Value::int(0, call.head),
-        call.head.with_string("unknown_quality"),
-        call.head.with_string("unknown_ids"),
-        call.head.with_string("unknown_reference_bases"),
-        call.head.with_string("unknown_alternate_bases"),
-        call.head.with_string("unknown_filters"),
-        call.head.with_string("unknown_info"),
-        call.head.with_string("unknown_genotypes"),
+        chrom,
+        Value::int(pos, call.head),
+        Value::int(rlen, call.head),
+        qual,
+        Value::list(ids, call.head),
+        call.head
+            .with_string(String::from_utf8_lossy(&reference_bases)),
+        Value::list(alternate_bases, call.head),
+        Value::list(filters, call.head),
+        Value::record(info, call.head),
+        genotypes_to_nuon(call, header, r)?,
     ];
 
-    vec_vals.extend_from_slice(&values_to_extend);
+    vec_vals.extend(values_to_extend);
+
+    Ok(())
 }
 
-/// Read a BCF header and return the header, stringmaps, and also the header in nuon format.
+/// Read a BCF header and return the header, and also the header in nuon format.
 fn read_bcf_header(
     reader: &mut BCFReader,
     call: &EvaluatedCall,
-) -> Result<(vcf::Header, StringMaps, Value), LabeledError> {
+) -> Result<(vcf::Header, Value), LabeledError> {
     // avoid repetitive code
     fn gzip_agnostic_reader<R: BufRead>(
         r: &mut bcf::io::Reader<R>,
         call: &EvaluatedCall,
-    ) -> Result<(vcf::Header, StringMaps, Value), LabeledError> {
+    ) -> Result<(vcf::Header, Value), LabeledError> {
         let raw_header = match r.read_header() {
             Ok(e) => e,
             Err(e) => {
-                return Err(LabeledError::new(format!("Could not read header. header unreadable due to {}", e)))
+                return Err(LabeledError::new(format!(
+                    "Could not read header. header unreadable due to {}",
+                    e
+                )))
             }
         };
 
         let header_nuon = parse_header(call, &raw_header);
-        // TODO: remove this unwrap
-        let string_maps = StringMaps::default(); // string_maps() method removed in new API
 
-        Ok((raw_header, string_maps, header_nuon))
+        Ok((raw_header, header_nuon))
     }
 
     match reader {
@@ -223,79 +539,75 @@ fn read_bcf_header(
 }
 
 /// Generic function for optional compression to iterate over the BCF records.
+///
+/// The FILTER, INFO and FORMAT string indices of the records are resolved
+/// through the string maps the reader keeps on the header.
 fn iterate_bcf_records<R: BufRead>(
     mut reader: bcf::io::Reader<R>,
     header: vcf::Header,
-    _string_maps: StringMaps,
     call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
+    sink: Sink,
 ) -> Result<(), LabeledError> {
+    let long = call.has_flag("long")?;
+
     for record in reader.records() {
         let r = match record {
             Ok(rec) => rec,
             Err(e) => {
-                return Err(LabeledError::new(format!("Record reading failed. cause of failure: {}", e)))
+                return Err(LabeledError::new(format!(
+                    "Record reading failed. cause of failure: {}",
+                    e
+                )))
             }
         };
 
         let mut vec_vals = Vec::new();
-        // Skipping record parsing due to API incompatibility
-        // add_record(call, r, &mut vec_vals);
+        add_record(call, &header, &r, &mut vec_vals).map_err(record_error)?;
 
         let record_inner =
             Record::from_iter(VCF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
 
-        value_records.push(Value::record(record_inner, call.head))
+        if !send_variant_row(call, record_inner, long, sink) {
+            break;
+        }
     }
 
     Ok(())
 }
 
-/// Parse a fasta file into a nushell structure.
+/// Parse a BCF file into a nushell structure.
 pub fn from_bcf_inner(
     call: &EvaluatedCall,
-    input: &Value,
+    signals: &Signals,
+    input: PipelineData,
     gz: Compression,
-) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val,
-        other => {
-            return Err(LabeledError::new(format!("Input should be binary. requires binary input, got {}", other.get_type())))
-        }
-    };
+) -> Result<PipelineData, LabeledError> {
+    let input = input_reader(input, call.head)?;
 
     let mut reader = match gz {
-        Compression::Uncompressed => {
-            BCFReader::Uncompressed(Box::new(bcf::io::Reader::new(stream.as_slice())))
-        }
+        Compression::Uncompressed => BCFReader::Uncompressed(Box::new(bcf::io::Reader::new(input))),
         Compression::Gzipped => {
-            let gz = bgzf::io::Reader::new(stream.as_slice());
+            let gz = bgzf::io::Reader::new(input);
             BCFReader::Compressed(Box::new(bcf::io::Reader::new(gz)))
         }
     };
 
-    let (header, string_maps, header_nuon) = read_bcf_header(&mut reader, call).unwrap();
+    let (header, header_nuon) = read_bcf_header(&mut reader, call)?;
 
-    let mut value_records = Vec::new();
+    let stream = call.has_flag("stream")?;
+    let call = call.clone();
 
     // now match on compression
-    match reader {
-        BCFReader::Uncompressed(uc) => {
-            iterate_bcf_records(*uc, header, string_maps, call, &mut value_records).unwrap();
-        }
-        BCFReader::Compressed(c) => {
-            iterate_bcf_records(*c, header, string_maps, call, &mut value_records).unwrap();
-        }
-    }
-
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head),
-        },
+    header_body_output(
         call.head,
-    ))
+        signals,
+        stream,
+        header_nuon,
+        move |sink| match reader {
+            BCFReader::Uncompressed(uc) => iterate_bcf_records(*uc, header, &call, sink),
+            BCFReader::Compressed(c) => iterate_bcf_records(*c, header, &call, sink),
+        },
+    )
 }
 
 /// Read a VCF header and return the header, stringmaps, and also the header in nuon format.
@@ -312,7 +624,10 @@ fn read_vcf_header(
         let raw_header = match r.read_header() {
             Ok(rh) => rh,
             Err(e) => {
-                return Err(LabeledError::new(format!("Failed to read raw VCF header. cause of failure: {}", e)))
+                return Err(LabeledError::new(format!(
+                    "Failed to read raw VCF header. cause of failure: {}",
+                    e
+                )))
             }
         };
 
@@ -332,72 +647,737 @@ fn iterate_vcf_records<R: BufRead>(
     mut reader: vcf::io::Reader<R>,
     header: vcf::Header,
     call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
+    sink: Sink,
 ) -> Result<(), LabeledError> {
+    let long = call.has_flag("long")?;
+
     for record in reader.records() {
         let r = match record {
             Ok(rec) => rec,
             Err(e) => {
-                return Err(LabeledError::new(format!("Record reading failed. cause of failure: {}", e)))
+                return Err(LabeledError::new(format!(
+                    "Record reading failed. cause of failure: {}",
+                    e
+                )))
             }
         };
 
         let mut vec_vals = Vec::new();
-        // Skipping record parsing due to API incompatibility
-        // add_record(call, r, &mut vec_vals);
+        add_record(call, &header, &r, &mut vec_vals).map_err(record_error)?;
 
         let vec_vals_inner =
             Record::from_iter(VCF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
 
-        value_records.push(Value::record(vec_vals_inner, call.head))
+        if !send_variant_row(call, vec_vals_inner, long, sink) {
+            break;
+        }
     }
 
     Ok(())
 }
 
-/// Parse a fasta file into a nushell structure.
+/// Parse a VCF file into a nushell structure.
 pub fn from_vcf_inner(
     call: &EvaluatedCall,
-    input: &Value,
+    signals: &Signals,
+    input: PipelineData,
     gz: Compression,
-) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val.clone(),
-        Value::String { val, .. } => val.as_bytes().to_vec(),
-        _ => return Err(LabeledError::new("Input must be binary or string data")),
-    };
+) -> Result<PipelineData, LabeledError> {
+    let input = input_reader(input, call.head)?;
 
     let mut reader = match gz {
-        Compression::Uncompressed => VCFReader::Uncompressed(Box::new(vcf::io::Reader::new(stream.as_slice()))),
+        Compression::Uncompressed => {
+            VCFReader::Uncompressed(Box::new(vcf::io::Reader::new(BufReader::new(input))))
+        }
         Compression::Gzipped => {
-            let gz = bgzf::io::Reader::new(stream.as_slice());
+            let gz = bgzf::io::Reader::new(input);
             VCFReader::Compressed(Box::new(vcf::io::Reader::new(BufReader::new(gz))))
         }
     };
 
-    let (header, header_nuon) = match read_vcf_header(&mut reader, call) {
-        Ok(h) => h,
-        Err(e) => return Err(e),
-    };
+    let (header, header_nuon) = read_vcf_header(&mut reader, call)?;
 
-    let mut value_records = Vec::new();
+    let stream = call.has_flag("stream")?;
+    let call = call.clone();
 
     // now match on compression
-    match reader {
-        VCFReader::Uncompressed(uc) => {
-            iterate_vcf_records(*uc, header, call, &mut value_records).unwrap();
+    header_body_output(
+        call.head,
+        signals,
+        stream,
+        header_nuon,
+        move |sink| match reader {
+            VCFReader::Uncompressed(uc) => iterate_vcf_records(*uc, header, &call, sink),
+            VCFReader::Compressed(c) => iterate_vcf_records(*c, header, &call, sink),
+        },
+    )
+}
+
+/// Query a BGZF compressed VCF for the variants overlapping `region`, using
+/// its `.tbi` (or `.csi`) index to seek straight to them.
+///
+/// The header is read from the start of the file, then only the blocks the
+/// index points to are decoded.
+pub fn query_vcf_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    path: PathBuf,
+    region: &str,
+) -> Result<ListStream, LabeledError> {
+    let region = parse_region(region)?;
+    let index = read_tabix_index(&path)?;
+    let long = call.has_flag("long")?;
+    let call = call.clone();
+
+    Ok(stream_values(call.head, signals, move |sink| {
+        let mut header_reader = File::open(&path)
+            .map(|f| vcf::io::Reader::new(BufReader::new(bgzf::io::Reader::new(f))))
+            .map_err(|e| {
+                LabeledError::new(format!(
+                    "Could not open {}. cause of failure: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+
+        let header = header_reader.read_header().map_err(|e| {
+            LabeledError::new(format!(
+                "Failed to read raw VCF header. cause of failure: {}",
+                e
+            ))
+        })?;
+
+        let mut r = vcf::Record::default();
+
+        query_tabix_lines(
+            &path,
+            index.as_ref(),
+            TabixFormat::Vcf,
+            &region,
+            &mut |line| {
+                vcf::io::Reader::new(line.as_bytes())
+                    .read_record(&mut r)
+                    .map_err(|e| {
+                        LabeledError::new(format!("Record reading failed. cause of failure: {}", e))
+                    })?;
+
+                let mut vec_vals = Vec::new();
+                add_record(&call, &header, &r, &mut vec_vals).map_err(record_error)?;
+
+                let record_inner =
+                    Record::from_iter(VCF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+
+                Ok(send_variant_row(&call, record_inner, long, sink))
+            },
+        )
+    }))
+}
+
+/// Get a column of a variant row, or say which row is missing it.
+fn row_field<'a>(row: &'a Record, column: &str, i: usize) -> Result<&'a Value, LabeledError> {
+    row.get(column)
+        .ok_or_else(|| LabeledError::new(format!("Row {i} is missing the {column} column")))
+}
+
+/// Format a single value for the VCF body, where nothing is missing (`.`).
+fn value_to_vcf(value: &Value) -> Result<String, LabeledError> {
+    let text = match value {
+        Value::Nothing { .. } => ".".into(),
+        Value::Int { val, .. } => val.to_string(),
+        Value::Float { val, .. } => val.to_string(),
+        Value::String { val, .. } => val.clone(),
+        Value::List { vals, .. } => vals
+            .iter()
+            .map(value_to_vcf)
+            .collect::<Result<Vec<_>, _>>()?
+            .join(","),
+        other => {
+            return Err(LabeledError::new(format!(
+                "Can't write a {} as a VCF value",
+                other.get_type()
+            )))
         }
-        VCFReader::Compressed(c) => {
-            iterate_vcf_records(*c, header, call, &mut value_records).unwrap();
+    };
+
+    Ok(text)
+}
+
+/// Join a list column, or `.` if it is empty.
+fn list_to_vcf(value: &Value, separator: &str) -> Result<String, LabeledError> {
+    let values = value
+        .as_list()?
+        .iter()
+        .map(value_to_vcf)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(match values.is_empty() {
+        true => ".".into(),
+        false => values.join(separator),
+    })
+}
+
+/// Format the `info` record, where flags are `true` and keys without a value
+/// are nothing.
+fn info_to_vcf(info: &Record) -> Result<String, LabeledError> {
+    let mut fields = Vec::new();
+
+    for (key, value) in info.iter() {
+        match value {
+            Value::Bool { val: true, .. } | Value::Nothing { .. } => fields.push(key.clone()),
+            Value::Bool { val: false, .. } => (),
+            other => fields.push(format!("{key}={}", value_to_vcf(other)?)),
         }
     }
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head),
-        },
-        call.head,
-    ))
+    Ok(match fields.is_empty() {
+        true => ".".into(),
+        false => fields.join(";"),
+    })
+}
+
+/// Format a genotype record (`{alleles, phased}`) as GT text, e.g. `0|1`.
+fn genotype_to_vcf(genotype: &Record) -> Result<String, LabeledError> {
+    let separator = match genotype.get("phased") {
+        Some(Value::Bool { val: true, .. }) => "|",
+        _ => "/",
+    };
+
+    match genotype.get("alleles") {
+        Some(alleles) => list_to_vcf(alleles, separator),
+        None => Err(LabeledError::new("Genotype is missing its alleles")),
+    }
+}
+
+/// Format the FORMAT column and each sample's column from the `genotypes`
+/// record, in the order of the header's sample names.
+fn samples_to_vcf(
+    genotypes: &Record,
+    sample_names: &[String],
+    i: usize,
+) -> Result<Vec<String>, LabeledError> {
+    if sample_names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let samples = sample_names
+        .iter()
+        .map(|name| match genotypes.get(name) {
+            Some(sample) => sample.as_record().map_err(LabeledError::from),
+            None => Err(LabeledError::new(format!(
+                "Row {i} has no genotypes for sample {name}"
+            ))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // every sample shares the keys of the FORMAT column.
+    let keys: Vec<&String> = samples[0].columns().collect();
+    let mut columns = vec![match keys.is_empty() {
+        true => ".".to_string(),
+        false => keys
+            .iter()
+            .map(|k| k.as_str())
+            .collect::<Vec<_>>()
+            .join(":"),
+    }];
+
+    for sample in samples {
+        let fields = keys
+            .iter()
+            .map(|key| match sample.get(*key) {
+                Some(Value::Record { val, .. }) => genotype_to_vcf(val),
+                Some(value) => value_to_vcf(value),
+                None => Ok(".".into()),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        columns.push(fields.join(":"));
+    }
+
+    Ok(columns)
+}
+
+/// Format a row of [`VCF_COLUMNS`] as a VCF line.
+fn variant_line_from_nuon(
+    row: &Record,
+    sample_names: &[String],
+    i: usize,
+) -> Result<String, LabeledError> {
+    if row.contains("sample") && !row.contains("genotypes") {
+        return Err(LabeledError::new(format!(
+            "Row {i} is in the long (one row per sample) layout, which can't be written back"
+        )));
+    }
+
+    let info = row_field(row, "info", i)?.as_record()?;
+
+    let mut fields = vec![
+        row_field(row, "chrom", i)?.as_str()?.to_string(),
+        row_field(row, "pos", i)?.as_int()?.to_string(),
+        list_to_vcf(row_field(row, "id", i)?, ";")?,
+        row_field(row, "ref", i)?.as_str()?.to_string(),
+        list_to_vcf(row_field(row, "alt", i)?, ",")?,
+        value_to_vcf(row_field(row, "qual", i)?)?,
+        list_to_vcf(row_field(row, "filter", i)?, ";")?,
+        info_to_vcf(info)?,
+    ];
+
+    if !sample_names.is_empty() {
+        let genotypes = row_field(row, "genotypes", i)?.as_record()?;
+        fields.extend(samples_to_vcf(genotypes, sample_names, i)?);
+    }
+
+    Ok(fields.join("\t") + "\n")
+}
+
+/// Quote a header description, escaping quotes and backslashes.
+fn quote_description(description: &str) -> String {
+    format!(
+        "\"{}\"",
+        description.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+/// Get a string field from a header record, or nothing.
+fn header_text(record: &Record, key: &str) -> Option<String> {
+    match record.get(key) {
+        Some(Value::Nothing { .. }) | None => None,
+        Some(Value::String { val, .. }) => Some(val.clone()),
+        Some(other) => other.coerce_string().ok(),
+    }
+}
+
+/// Rebuild the meta lines and the `#CHROM` line from the header record made
+/// by [`parse_header`].
+///
+/// Also returns the sample names, so the genotypes can be written in order.
+fn header_text_from_nuon(header: &Record) -> Result<(String, Vec<String>), LabeledError> {
+    let mut text = String::new();
+
+    let section = |name: &str| -> Result<Record, LabeledError> {
+        match header.get(name) {
+            Some(Value::Record { val, .. }) => Ok(val.clone().into_owned()),
+            Some(Value::Nothing { .. }) | None => Ok(Record::new()),
+            Some(other) => Err(LabeledError::new(format!(
+                "Header {name} should be a record, got {}",
+                other.get_type()
+            ))),
+        }
+    };
+
+    let file_format = header
+        .get(HEADER_COLUMNS[0])
+        .and_then(|v| v.as_str().ok())
+        .unwrap_or("VCFv4.3");
+    text.push_str(&format!("##fileformat={file_format}\n"));
+
+    // INFO and FORMAT share a layout.
+    for (kind, column) in [("INFO", HEADER_COLUMNS[1]), ("FORMAT", HEADER_COLUMNS[3])] {
+        for (id, value) in section(column)?.iter() {
+            let map = value.as_record()?;
+            text.push_str(&format!(
+                "##{kind}=<ID={id},Number={},Type={},Description={}>\n",
+                header_text(map, "number").unwrap_or(".".into()),
+                header_text(map, "type").unwrap_or("String".into()),
+                quote_description(&header_text(map, "description").unwrap_or_default()),
+            ));
+        }
+    }
+
+    // FILTER and ALT only have a description.
+    for (kind, column) in [("FILTER", HEADER_COLUMNS[2]), ("ALT", HEADER_COLUMNS[4])] {
+        for (id, value) in section(column)?.iter() {
+            text.push_str(&format!(
+                "##{kind}=<ID={id},Description={}>\n",
+                quote_description(
+                    &header_text(value.as_record()?, "description").unwrap_or_default()
+                ),
+            ));
+        }
+    }
+
+    for (id, value) in section(HEADER_COLUMNS[5])?.iter() {
+        let contig = value.as_record()?;
+        let mut line = format!("##contig=<ID={id}");
+        // the length, then any other fields; unknown lengths are nothing.
+        for key in contig.columns() {
+            if let Some(field) = header_text(contig, key) {
+                line.push_str(&format!(",{key}={}", meta_field_text(&field)));
+            }
+        }
+        text.push_str(&line);
+        text.push_str(">\n");
+    }
+
+    for (key, values) in section(HEADER_COLUMNS[7])?.iter() {
+        for value in values.as_list()? {
+            text.push_str(&format!("##{key}={}\n", value.as_str()?));
+        }
+    }
+
+    let sample_names = match header.get(HEADER_COLUMNS[6]) {
+        Some(Value::List { vals, .. }) => vals
+            .iter()
+            .map(|v| v.as_str().map(String::from).map_err(LabeledError::from))
+            .collect::<Result<Vec<_>, _>>()?,
+        _ => Vec::new(),
+    };
+
+    text.push_str("#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO");
+    if !sample_names.is_empty() {
+        text.push_str("\tFORMAT");
+        for name in &sample_names {
+            text.push('\t');
+            text.push_str(name);
+        }
+    }
+    text.push('\n');
+
+    Ok((text, sample_names))
+}
+
+/// Rebuild the VCF text from the `{header, body}` record made by `from vcf`
+/// or `from bcf`.
+fn vcf_text_from_nuon(input: &Value) -> Result<String, LabeledError> {
+    let input = input.as_record()?;
+
+    let header = match input.get("header") {
+        Some(h) => h.as_record()?,
+        None => return Err(LabeledError::new(
+            "Input has no header: expected the {header, body} record from `from vcf` or `from bcf`",
+        )),
+    };
+    let body = match input.get("body") {
+        Some(b) => b.as_list()?,
+        None => return Err(LabeledError::new(
+            "Input has no body: expected the {header, body} record from `from vcf` or `from bcf`",
+        )),
+    };
+
+    let (mut text, sample_names) = header_text_from_nuon(header)?;
+
+    for (i, row) in body.iter().enumerate() {
+        text.push_str(&variant_line_from_nuon(row.as_record()?, &sample_names, i)?);
+    }
+
+    Ok(text)
+}
+
+/// Read the rebuilt VCF text back into a header and records, so they are
+/// validated by noodles before being written out.
+fn read_variants(text: &str) -> Result<(vcf::Header, Vec<vcf::Record>), LabeledError> {
+    let mut reader = vcf::io::Reader::new(text.as_bytes());
+    let header = reader
+        .read_header()
+        .map_err(|e| LabeledError::new(format!("Could not rebuild the VCF header: {e}")))?;
+
+    let records = reader
+        .records()
+        .enumerate()
+        .map(|(i, result)| {
+            result.map_err(|e| LabeledError::new(format!("Could not rebuild row {i}: {e}")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((header, records))
+}
+
+/// Write the header and records with any variant writer.
+fn write_variants<W: VariantWrite>(
+    writer: &mut W,
+    header: &vcf::Header,
+    records: &[vcf::Record],
+) -> Result<(), LabeledError> {
+    writer
+        .write_variant_header(header)
+        .map_err(|e| LabeledError::new(format!("Could not write the header: {e}")))?;
+
+    for (i, record) in records.iter().enumerate() {
+        writer
+            .write_variant_record(header, record)
+            .map_err(|e| LabeledError::new(format!("Could not write row {i}: {e}")))?;
+    }
+
+    Ok(())
+}
+
+/// Go from the `{header, body}` record of `from vcf`/`from bcf` to VCF text,
+/// or to BGZF compressed VCF (which `tabix` can index).
+pub fn nuon_to_vcf(
+    call: &EvaluatedCall,
+    input: &Value,
+    gz: Compression,
+) -> Result<Value, LabeledError> {
+    let (header, records) = read_variants(&vcf_text_from_nuon(input)?)?;
+
+    match gz {
+        Compression::Uncompressed => {
+            let mut writer = vcf::io::Writer::new(Vec::new());
+            write_variants(&mut writer, &header, &records)?;
+
+            let out_final = String::from_utf8(writer.into_inner()).map_err(|err| {
+                LabeledError::new(format!("Can't format bytes as UTF-8: {}", err))
+            })?;
+
+            Ok(Value::string(out_final, call.head))
+        }
+        Compression::Gzipped => {
+            let mut writer = vcf::io::Writer::new(bgzf::io::Writer::new(Vec::new()));
+            write_variants(&mut writer, &header, &records)?;
+
+            let out = writer
+                .into_inner()
+                .finish()
+                .map_err(|e| LabeledError::new(format!("Could not finish the BGZF stream: {e}")))?;
+
+            Ok(Value::binary(out, call.head))
+        }
+    }
+}
+
+/// Go from the `{header, body}` record of `from vcf`/`from bcf` to BCF.
+///
+/// The string maps the records are encoded against are derived from the
+/// header by the writer.
+pub fn nuon_to_bcf(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let (header, records) = read_variants(&vcf_text_from_nuon(input)?)?;
+
+    let mut writer = bcf::io::Writer::new(Vec::new());
+    write_variants(&mut writer, &header, &records)?;
+
+    let out = writer
+        .into_inner()
+        .finish()
+        .map_err(|e| LabeledError::new(format!("Could not finish the BGZF stream: {e}")))?;
+
+    Ok(Value::binary(out, call.head))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bio_format::tests::{call_with_switches, fixture, test_call};
+    use nu_protocol::Span;
+
+    const VCF: &str = "##fileformat=VCFv4.3
+##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">
+##INFO=<ID=AF,Number=A,Type=Float,Description=\"Allele frequency\">
+##INFO=<ID=DB,Number=0,Type=Flag,Description=\"In dbSNP\">
+##FILTER=<ID=q10,Description=\"Quality below 10\">
+##FORMAT=<ID=GT,Number=1,Type=String,Description=\"Genotype\">
+##FORMAT=<ID=DP,Number=1,Type=Integer,Description=\"Depth\">
+##contig=<ID=chr1,length=1000>
+#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\tFORMAT\ts1\ts2
+chr1\t10\trs1;rs2\tA\tC,T\t29.5\tq10\tDP=14;AF=0.5,0.25;DB\tGT:DP\t0|1:3\t1/1:.
+";
+
+    fn from_vcf(bytes: Vec<u8>, gz: Compression) -> Value {
+        from_vcf_with(&test_call(), bytes, gz)
+    }
+
+    fn from_vcf_with(call: &EvaluatedCall, bytes: Vec<u8>, gz: Compression) -> Value {
+        let input = PipelineData::Value(Value::test_binary(bytes), None);
+        from_vcf_inner(call, &Signals::empty(), input, gz)
+            .and_then(|output| Ok(output.into_value(Span::test_data())?))
+            .unwrap()
+    }
+
+    fn first_row(vcf: &Value) -> Value {
+        vcf.get_data_by_key("body").unwrap().as_list().unwrap()[0].clone()
+    }
+
+    fn fixture_header() -> vcf::Header {
+        let path = fixture("map.vcf.gz");
+        let mut reader = vcf::io::Reader::new(BufReader::new(bgzf::io::Reader::new(
+            File::open(path).unwrap(),
+        )));
+        reader.read_header().unwrap()
+    }
+
+    /// The string in a header section, e.g. the Number of the DP INFO field.
+    fn header_string(header: &Value, path: &[&str]) -> String {
+        path.iter()
+            .fold(header.clone(), |v, key| v.get_data_by_key(key).unwrap())
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn header_values_are_as_written_in_the_vcf() {
+        let header = parse_header(&test_call(), &fixture_header());
+
+        assert_eq!(header_string(&header, &["file_format"]), "VCFv4.2");
+        assert_eq!(header_string(&header, &["info", "DP", "number"]), "1");
+        assert_eq!(header_string(&header, &["info", "DP", "type"]), "Integer");
+        assert_eq!(
+            header_string(&header, &["info", "DP", "description"]),
+            "Raw read depth"
+        );
+        assert_eq!(header_string(&header, &["info", "AC", "number"]), "A");
+        assert_eq!(header_string(&header, &["format", "PL", "number"]), "G");
+    }
+
+    #[test]
+    fn contig_without_length_has_nothing() {
+        let text = "##fileformat=VCFv4.3\n##contig=<ID=chr1>\n#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n";
+        let raw_header = vcf::io::Reader::new(text.as_bytes()).read_header().unwrap();
+
+        let header = parse_header(&test_call(), &raw_header);
+        let length = header
+            .get_data_by_key("contig")
+            .and_then(|c| c.get_data_by_key("chr1"))
+            .and_then(|c| c.get_data_by_key("length"))
+            .unwrap();
+
+        assert!(length.is_nothing());
+    }
+
+    #[test]
+    fn other_meta_lines_are_kept() {
+        let header = parse_header(&test_call(), &fixture_header());
+        let reference = header
+            .get_data_by_key("other")
+            .and_then(|o| o.get_data_by_key("reference"))
+            .unwrap();
+        assert_eq!(
+            reference.as_list().unwrap()[0].as_str().unwrap(),
+            "file://./drAilAlti1.fa"
+        );
+
+        let (text, _) = header_text_from_nuon(header.as_record().unwrap()).unwrap();
+        assert!(text.contains("##reference=file://./drAilAlti1.fa\n"));
+        assert!(text.contains("##bcftoolsVersion=1.17+htslib-1.17\n"));
+    }
+
+    #[test]
+    fn vcf_round_trip() {
+        let vcf = from_vcf(
+            std::fs::read(fixture("map.vcf.gz")).unwrap(),
+            Compression::Gzipped,
+        );
+        assert!(!vcf
+            .get_data_by_key("body")
+            .unwrap()
+            .as_list()
+            .unwrap()
+            .is_empty());
+
+        let text = nuon_to_vcf(&test_call(), &vcf, Compression::Uncompressed).unwrap();
+        let bytes = text.as_str().unwrap().as_bytes().to_vec();
+        assert_eq!(from_vcf(bytes, Compression::Uncompressed), vcf);
+
+        let gz = nuon_to_vcf(&test_call(), &vcf, Compression::Gzipped).unwrap();
+        let bytes = gz.as_binary().unwrap().to_vec();
+        assert_eq!(from_vcf(bytes, Compression::Gzipped), vcf);
+    }
+
+    #[test]
+    fn bcf_round_trip() {
+        let bcf = |bytes: Vec<u8>| {
+            let input = PipelineData::Value(Value::test_binary(bytes), None);
+            from_bcf_inner(
+                &test_call(),
+                &Signals::empty(),
+                input,
+                Compression::Uncompressed,
+            )
+            .and_then(|output| Ok(output.into_value(Span::test_data())?))
+            .unwrap()
+        };
+
+        let original = bcf(std::fs::read(fixture("map.bcf")).unwrap());
+        assert!(!original
+            .get_data_by_key("body")
+            .unwrap()
+            .as_list()
+            .unwrap()
+            .is_empty());
+
+        let written = nuon_to_bcf(&test_call(), &original).unwrap();
+        assert_eq!(bcf(written.as_binary().unwrap().to_vec()), original);
+    }
+
+    #[test]
+    fn variant_columns_are_typed() {
+        let vcf = from_vcf(VCF.as_bytes().to_vec(), Compression::Uncompressed);
+        let row = first_row(&vcf);
+        let get = |key: &str| row.get_data_by_key(key).unwrap();
+        let strings = |value: Value| -> Vec<String> {
+            value
+                .as_list()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_str().unwrap().to_string())
+                .collect()
+        };
+
+        assert_eq!(get("pos").as_int().unwrap(), 10);
+        assert_eq!(get("qual").as_float().unwrap(), 29.5);
+        assert_eq!(strings(get("id")), ["rs1", "rs2"]);
+        assert_eq!(strings(get("alt")), ["C", "T"]);
+        assert_eq!(strings(get("filter")), ["q10"]);
+
+        let info = get("info");
+        assert_eq!(info.get_data_by_key("DP").unwrap().as_int().unwrap(), 14);
+        let frequencies: Vec<f64> = info
+            .get_data_by_key("AF")
+            .unwrap()
+            .as_list()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_float().unwrap())
+            .collect();
+        assert_eq!(frequencies, [0.5, 0.25]);
+        assert!(info.get_data_by_key("DB").unwrap().as_bool().unwrap());
+    }
+
+    #[test]
+    fn genotypes_per_sample() {
+        let vcf = from_vcf(VCF.as_bytes().to_vec(), Compression::Uncompressed);
+        let genotypes = first_row(&vcf).get_data_by_key("genotypes").unwrap();
+        let sample = |name: &str| genotypes.get_data_by_key(name).unwrap();
+        let alleles = |gt: Value| -> Vec<i64> {
+            gt.get_data_by_key("alleles")
+                .unwrap()
+                .as_list()
+                .unwrap()
+                .iter()
+                .map(|v| v.as_int().unwrap())
+                .collect()
+        };
+
+        let s1 = sample("s1").get_data_by_key("GT").unwrap();
+        assert_eq!(alleles(s1.clone()), [0, 1]);
+        assert!(s1.get_data_by_key("phased").unwrap().as_bool().unwrap());
+        assert_eq!(
+            sample("s1")
+                .get_data_by_key("DP")
+                .unwrap()
+                .as_int()
+                .unwrap(),
+            3
+        );
+
+        let s2 = sample("s2").get_data_by_key("GT").unwrap();
+        assert_eq!(alleles(s2.clone()), [1, 1]);
+        assert!(!s2.get_data_by_key("phased").unwrap().as_bool().unwrap());
+        assert!(sample("s2").get_data_by_key("DP").unwrap().is_nothing());
+    }
+
+    #[test]
+    fn long_layout_has_a_row_per_sample() {
+        let call = call_with_switches(&["long"]);
+        let vcf = from_vcf_with(&call, VCF.as_bytes().to_vec(), Compression::Uncompressed);
+        let rows = vcf.get_data_by_key("body").unwrap();
+        let rows = rows.as_list().unwrap();
+
+        assert_eq!(rows.len(), 2);
+        for (row, name) in rows.iter().zip(["s1", "s2"]) {
+            assert_eq!(
+                row.get_data_by_key("sample").unwrap().as_str().unwrap(),
+                name
+            );
+            assert_eq!(row.get_data_by_key("pos").unwrap().as_int().unwrap(), 10);
+            assert!(row.get_data_by_key("GT").is_some());
+        }
+    }
 }
@@ -1,14 +1,16 @@
-use noodles_bed as bed;
 use nu_plugin::EvaluatedCall;
 use nu_protocol::LabeledError;
-use nu_protocol::{Record, Value};
+use nu_protocol::{ListStream, PipelineData, Record, Signals, Value};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
 
-use super::SpanExt;
+use super::index::{query_tabix_lines, read_tabix_index, TabixFormat};
+use super::{input_reader, parse_region, stream_values, Sink, SpanExt};
 
-/// BED reader type
-const BED_COLUMN_NUMBER: u8 = 3;
+/// The largest number of standard BED columns (BED12).
+const MAX_BED_COLUMN_NUMBER: usize = 12;
 
-/// Columns in a BAM/SAM file
+/// Columns in a BED file
 pub const BED_COLUMNS: &[&str] = &[
     // Mandatory, name of chromosome
     "chrom",
@@ -18,33 +20,374 @@ pub const BED_COLUMNS: &[&str] = &[
     "chromEnd",
 ];
 
-pub fn from_bed_inner(call: &EvaluatedCall, input: Value) -> Result<Vec<Value>, LabeledError> {
-    let bytes = match input {
-        Value::Binary { val, .. } => val,
-        Value::String { val, .. } => val.as_bytes().to_vec(),
-        _ => return Err(LabeledError::new("Input must be binary or string data")),
+/// The optional BED columns, in the order they appear after the mandatory ones.
+pub const BED_OPTIONAL_COLUMNS: &[&str] = &[
+    "name",
+    "score",
+    "strand",
+    "thickStart",
+    "thickEnd",
+    "itemRgb",
+    "blockCount",
+    "blockSizes",
+    "blockStarts",
+];
+
+/// Lines that are not records: `track` and `browser` lines, and comments.
+fn is_header_line(line: &str) -> bool {
+    line.starts_with("track") || line.starts_with("browser") || line.starts_with('#')
+}
+
+/// Parse an integer field, reporting the line it came from on failure.
+fn parse_int(field: &str, column: &str, line_number: usize) -> Result<i64, LabeledError> {
+    field.parse::<i64>().map_err(|e| {
+        LabeledError::new(format!(
+            "Could not parse {column} on line {line_number} of the BED file: {e}"
+        ))
+    })
+}
+
+/// Parse a comma separated list of integers (blockSizes, blockStarts). A
+/// trailing comma is allowed.
+fn parse_int_list(
+    call: &EvaluatedCall,
+    field: &str,
+    column: &str,
+    line_number: usize,
+) -> Result<Value, LabeledError> {
+    let values = field
+        .split(',')
+        .filter(|e| !e.is_empty())
+        .map(|e| parse_int(e, column, line_number).map(|n| Value::int(n, call.head)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Value::list(values, call.head))
+}
+
+/// Convert a single field into a typed value, given its column.
+fn parse_field(
+    call: &EvaluatedCall,
+    field: &str,
+    column: &str,
+    line_number: usize,
+) -> Result<Value, LabeledError> {
+    let value = match column {
+        "chromStart" | "chromEnd" | "thickStart" | "thickEnd" | "blockCount" => {
+            Value::int(parse_int(field, column, line_number)?, call.head)
+        }
+        "blockSizes" | "blockStarts" => parse_int_list(call, field, column, line_number)?,
+        // score should be an int, but floats are common in the wild.
+        "score" => match (field.parse::<i64>(), field.parse::<f64>()) {
+            (Ok(n), _) => Value::int(n, call.head),
+            (_, Ok(f)) => Value::float(f, call.head),
+            _ => Value::nothing(call.head),
+        },
+        "strand" if field == "." => Value::nothing(call.head),
+        _ => call.head.with_string(field),
+    };
+
+    Ok(value)
+}
+
+/// Parse a BED line into a record. The number of columns is set by the first
+/// line parsed, and every later line must have at least as many.
+fn parse_bed_line(
+    call: &EvaluatedCall,
+    line: &str,
+    column_number: &mut Option<usize>,
+    line_number: usize,
+) -> Result<Value, LabeledError> {
+    let columns = BED_COLUMNS.iter().chain(BED_OPTIONAL_COLUMNS.iter());
+
+    let fields: Vec<&str> = line.split('\t').collect();
+
+    let n = *column_number.get_or_insert(fields.len().min(MAX_BED_COLUMN_NUMBER));
+
+    if n < BED_COLUMNS.len() {
+        return Err(LabeledError::new(format!(
+            "BED records need at least {} columns, found {n} on line {line_number}",
+            BED_COLUMNS.len()
+        )));
+    }
+
+    if fields.len() < n {
+        return Err(LabeledError::new(format!(
+            "Expected {n} columns (BED{n}) on line {line_number}, found {}",
+            fields.len()
+        )));
+    }
+
+    let row = columns
+        .zip(fields.iter())
+        .take(n)
+        .map(|(column, field)| {
+            parse_field(call, field, column, line_number).map(|v| (column.to_string(), v))
+        })
+        .collect::<Result<Record, LabeledError>>()?;
+
+    Ok(Value::record(row, call.head))
+}
+
+/// Read a BED file line by line, sending each record to the sink.
+fn iterate_bed_records<R: BufRead>(
+    reader: R,
+    call: &EvaluatedCall,
+    sink: Sink,
+) -> Result<(), LabeledError> {
+    // set from the first data line.
+    let mut column_number = None;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line.map_err(|e| {
+            LabeledError::new(format!(
+                "Failed reading line {line_number} in the BED file: {e}"
+            ))
+        })?;
+
+        if line.trim().is_empty() || is_header_line(&line) {
+            continue;
+        }
+
+        if !sink(parse_bed_line(
+            call,
+            &line,
+            &mut column_number,
+            line_number,
+        )?) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a BED file into a lazy stream of records.
+pub fn from_bed_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    input: PipelineData,
+) -> Result<ListStream, LabeledError> {
+    let reader = BufReader::new(input_reader(input, call.head)?);
+    let call = call.clone();
+
+    Ok(stream_values(call.head, signals, move |sink| {
+        iterate_bed_records(reader, &call, sink)
+    }))
+}
+
+/// Query a BGZF compressed BED for the intervals overlapping `region`, using
+/// its `.tbi` (or `.csi`) index.
+///
+/// Line numbers in errors count the lines returned by the query.
+pub fn query_bed_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    path: PathBuf,
+    region: &str,
+) -> Result<ListStream, LabeledError> {
+    let region = parse_region(region)?;
+    let index = read_tabix_index(&path)?;
+    let call = call.clone();
+
+    Ok(stream_values(call.head, signals, move |sink| {
+        let mut column_number = None;
+        let mut line_number = 0;
+
+        query_tabix_lines(
+            &path,
+            index.as_ref(),
+            TabixFormat::Bed,
+            &region,
+            &mut |line| {
+                line_number += 1;
+                if is_header_line(line) {
+                    return Ok(true);
+                }
+                Ok(sink(parse_bed_line(
+                    &call,
+                    line,
+                    &mut column_number,
+                    line_number,
+                )?))
+            },
+        )
+    }))
+}
+
+/// Format a single cell of a BED row.
+fn field_to_bed(value: &Value, column: &str, i: usize) -> Result<String, LabeledError> {
+    let text = match (column, value) {
+        ("score", Value::Nothing { .. }) => "0".into(),
+        (_, Value::Nothing { .. }) => ".".into(),
+        (_, Value::Int { val, .. }) => val.to_string(),
+        (_, Value::Float { val, .. }) => val.to_string(),
+        (_, Value::String { val, .. }) => val.clone(),
+        (_, Value::List { vals, .. }) => vals
+            .iter()
+            .map(|v| {
+                v.as_int()
+                    .map(|n| n.to_string())
+                    .map_err(LabeledError::from)
+            })
+            .collect::<Result<Vec<_>, _>>()?
+            .join(","),
+        (_, other) => {
+            return Err(LabeledError::new(format!(
+                "Row {i} has an unsupported {} in column {column}",
+                other.get_type()
+            )))
+        }
     };
 
-    let mut reader: bed::io::Reader<3, &[u8]> = bed::io::Reader::new(bytes.as_slice());
+    Ok(text)
+}
+
+/// Format a table with the [`BED_COLUMNS`] (and any leading run of the
+/// [`BED_OPTIONAL_COLUMNS`]) as a BED file.
+///
+/// The number of columns written (BED3, BED6, BED12...) is taken from the
+/// columns of the first row.
+pub fn nuon_to_bed(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let rows = input.as_list()?;
+
+    let columns: Vec<&str> = match rows.first() {
+        Some(first) => {
+            let first = first.as_record()?;
+            BED_COLUMNS
+                .iter()
+                .copied()
+                .chain(
+                    BED_OPTIONAL_COLUMNS
+                        .iter()
+                        .copied()
+                        .take_while(|c| first.contains(c)),
+                )
+                .collect()
+        }
+        None => BED_COLUMNS.to_vec(),
+    };
+
+    let mut out = String::new();
+
+    for (i, row) in rows.iter().enumerate() {
+        let row = row.as_record()?;
+
+        let position = |column: &str| -> Result<i64, LabeledError> {
+            match row.get(column) {
+                Some(v) => v.as_int().map_err(|e| {
+                    LabeledError::new(format!("Row {i} has a malformed {column}: {e}"))
+                }),
+                None => Err(LabeledError::new(format!(
+                    "Row {i} is missing the {column} column"
+                ))),
+            }
+        };
+
+        let (start, end) = (position(BED_COLUMNS[1])?, position(BED_COLUMNS[2])?);
+        if start < 0 || start >= end {
+            return Err(LabeledError::new(format!(
+                "Row {i} has chromStart {start} and chromEnd {end}, but 0 <= chromStart < chromEnd is required"
+            )));
+        }
+
+        let fields = columns
+            .iter()
+            .map(|column| match row.get(*column) {
+                Some(value) => field_to_bed(value, column, i),
+                None => Err(LabeledError::new(format!(
+                    "Row {i} is missing the {column} column (BED{})",
+                    columns.len()
+                ))),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        out.push_str(&fields.join("\t"));
+        out.push('\n');
+    }
+
+    Ok(Value::string(out, call.head))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bio_format::tests::{collect, test_call, try_collect};
+
+    fn from_bed(text: &str) -> Vec<Value> {
+        let input = PipelineData::Value(Value::test_string(text), None);
+        collect(from_bed_inner(&test_call(), &Signals::empty(), input).unwrap())
+    }
+
+    #[test]
+    fn bed_round_trip_keeps_the_column_count() {
+        for text in [
+            "chr1\t10\t20\nchr2\t0\t5\n",
+            "chr1\t10\t20\tfeature\t500\t+\n",
+            "chr1\t10\t100\tgene\t0\t-\t15\t90\t255,0,0\t2\t10,20\t0,70\n",
+        ] {
+            let rows = from_bed(text);
+            let bed = nuon_to_bed(&test_call(), &Value::test_list(rows)).unwrap();
+            assert_eq!(bed.as_str().unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn start_after_end_is_an_error() {
+        let rows = from_bed("chr1\t10\t20\n");
+        let mut row = rows[0].as_record().unwrap().clone();
+        row.insert("chromStart", Value::test_int(30));
 
-    let mut records = Vec::new();
+        let table = Value::test_list(vec![Value::test_record(row)]);
+        assert!(nuon_to_bed(&test_call(), &table).is_err());
+    }
 
-    // BED API has changed - temporarily disabled
-    /*for result in reader.records::<BED_COLUMN_NUMBER>() {
-        let record = result.map_err(|e| LabeledError::new(format!("Failed reading a record in the BED file: {e}")))?;
+    fn try_from_bed(text: &str) -> Result<Vec<Value>, LabeledError> {
+        let input = PipelineData::Value(Value::test_string(text), None);
+        try_collect(from_bed_inner(&test_call(), &Signals::empty(), input)?)
+    }
 
-        let mut row = Vec::new();
+    #[test]
+    fn columns_are_detected_from_the_first_line() {
+        let rows = from_bed(
+            "track name=test
+browser position chr1:1-100
+# a comment
+chr1\t10\t20\tfeature\t500\t.
+chr2\t0\t5\tother\t1.5\t+\textra
+",
+        );
+        assert_eq!(rows.len(), 2);
 
-        row.push(call.head.with_string(record.reference_sequence_name()));
-        let start: usize = record.start_position().into();
-        row.push(Value::int(start as i64, call.head));
-        let end: usize = record.end_position().into();
-        row.push(Value::int(end as i64, call.head));
+        let columns: Vec<String> = rows[1].as_record().unwrap().columns().cloned().collect();
+        assert_eq!(
+            columns,
+            ["chrom", "chromStart", "chromEnd", "name", "score", "strand"]
+        );
 
-        let record_inner = Record::from_iter(BED_COLUMNS.iter().map(|e| e.to_string()).zip(row));
+        let get = |row: &Value, key: &str| row.get_data_by_key(key).unwrap();
+        assert_eq!(get(&rows[0], "chromStart").as_int().unwrap(), 10);
+        assert_eq!(get(&rows[0], "score").as_int().unwrap(), 500);
+        assert!(get(&rows[0], "strand").is_nothing());
+        assert_eq!(get(&rows[1], "score").as_float().unwrap(), 1.5);
+    }
 
-        records.push(Value::record(record_inner, call.head))
-    }*/
+    #[test]
+    fn bed12_blocks_are_lists() {
+        let rows = from_bed("chr1\t10\t100\tgene\t0\t-\t15\t90\t255,0,0\t2\t10,20,\t0,70\n");
+        let sizes: Vec<i64> = rows[0]
+            .get_data_by_key("blockSizes")
+            .unwrap()
+            .as_list()
+            .unwrap()
+            .iter()
+            .map(|v| v.as_int().unwrap())
+            .collect();
+        assert_eq!(sizes, [10, 20]);
+    }
 
-    Ok(records)
+    #[test]
+    fn fewer_columns_than_the_first_line_is_an_error() {
+        assert!(try_from_bed("chr1\t10\t20\tfeature\nchr1\t30\t40\n").is_err());
+    }
 }
@@ -1,82 +1,302 @@
 /// The CRAM format
 use noodles_cram as cram;
+use noodles_fasta as fasta;
 use noodles_sam as sam;
 use nu_plugin::EvaluatedCall;
 use nu_protocol::LabeledError;
-use nu_protocol::{record, Record, Value};
-use crate::bio_format::SpanExt;
+use nu_protocol::{ListStream, PipelineData, Record, Signals, Value};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
 
-use crate::bio_format::bam::{create_record_values, parse_header, BAM_COLUMNS};
-// TODO: also allow the reference to be passed, so we can view the alignment sequences?
+use crate::bio_format::bam::{
+    create_record_values, iterate_records, parse_header, RecordOptions, BAM_COLUMNS,
+};
+use crate::bio_format::{
+    find_index, header_body_output, input_reader, parse_region, sidecar_path, stream_values,
+};
+
+/// Build a reference sequence repository from a fasta file. If there is a
+/// `.fai` next to it, sequences are read on demand, otherwise the whole fasta
+/// is loaded.
+pub fn reference_repository(path: &Path) -> Result<fasta::Repository, LabeledError> {
+    let reference_error = |e: io::Error| {
+        LabeledError::new(format!(
+            "Could not read reference fasta {}. cause of failure: {}",
+            path.display(),
+            e
+        ))
+    };
+
+    if sidecar_path(path, "fai").exists() {
+        let reader = fasta::io::indexed_reader::Builder::default()
+            .build_from_path(path)
+            .map_err(reference_error)?;
+        let adapter = fasta::repository::adapters::IndexedReader::new(reader);
+
+        return Ok(fasta::Repository::new(adapter));
+    }
+
+    let file = File::open(path).map_err(reference_error)?;
+    let mut reader = fasta::io::Reader::new(BufReader::new(file));
+    let records = reader
+        .records()
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(reference_error)?;
+
+    Ok(fasta::Repository::new(records))
+}
+
+/// Added to decoding errors: reference-based slices can't be decoded without
+/// the reference.
+pub fn reference_hint(reference: &Option<PathBuf>) -> &'static str {
+    match reference {
+        Some(_) => "",
+        None => " (this CRAM may need its reference, pass it with --reference)",
+    }
+}
+
+/// Run `decode` over the records, reporting a panic as a decoding error too,
+/// as noodles can panic on slices it can't resolve.
+pub fn decode_records<F>(decode: F, hint: &str) -> Result<(), LabeledError>
+where
+    F: FnOnce() -> Result<(), LabeledError>,
+{
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(decode))
+        .map_err(|_| LabeledError::new(format!("CRAM record decoding failed{}", hint)))?
+}
 
 /// Parse a CRAM file into a nushell structure.
-pub fn from_cram_inner(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val.clone(),
-        Value::String { val, .. } => val.as_bytes().to_vec(),
-        other => {
-            return Err(LabeledError::new(format!("Input must be binary or string data, got {}", other.get_type())))
-        }
+pub fn from_cram_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    input: PipelineData,
+    reference: Option<PathBuf>,
+) -> Result<PipelineData, LabeledError> {
+    let repository = match &reference {
+        Some(path) => reference_repository(path)?,
+        None => fasta::Repository::default(),
     };
 
-    let mut reader = cram::io::Reader::new(stream.as_slice());
+    let mut reader = cram::io::reader::Builder::default()
+        .set_reference_sequence_repository(repository)
+        .build_from_reader(input_reader(input, call.head)?);
 
     match reader.read_file_definition() {
         Ok(_) => (),
         Err(e) => {
-            return Err(LabeledError::new(format!("Could not read CRAM file definition. cause of failure: {}", e)))
+            return Err(LabeledError::new(format!(
+                "Could not read CRAM file definition. cause of failure: {}",
+                e
+            )))
         }
     };
 
     let header: sam::Header = match reader.read_file_header() {
         Ok(s) => s,
         Err(e) => {
-            return Err(LabeledError::new(format!("CRAM file header reading failed. cause of failure: {}", e)))
+            return Err(LabeledError::new(format!(
+                "CRAM file header reading failed. cause of failure: {}",
+                e
+            )))
         }
     };
 
     let header_nuon = parse_header(call, &header);
+    let options = RecordOptions::from_call(call)?;
+    let stream = call.has_flag("stream")?;
+    let call = call.clone();
+
+    let hint = reference_hint(&reference);
+
+    header_body_output(call.head, signals, stream, header_nuon, move |sink| {
+        let decode = || -> Result<(), LabeledError> {
+            for (i, result) in reader.records(&header).enumerate() {
+                let record = result.map_err(|e| {
+                    LabeledError::new(format!(
+                        "Could not decode CRAM record {}{}. cause of failure: {}",
+                        i + 1,
+                        hint,
+                        e
+                    ))
+                })?;
 
-    let mut value_records = Vec::new();
-
-    // CRAM files often require a reference sequence, but we'll try to read what we can
-    // If this fails, we'll return just the header with empty records
-    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        let mut temp_records = Vec::new();
-        for result in reader.records(&header) {
-            match result {
-                Ok(record) => {
-                    let vec_vals = create_record_values(call, record, &header);
-                    let records_inner = Record::from_iter(
-                        BAM_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals)
-                    );
-                    temp_records.push(Value::record(records_inner, call.head));
-                },
-                Err(_) => break, // Stop on first error rather than failing completely
+                let vec_vals = create_record_values(&call, record, &header, options)?;
+                let records_inner =
+                    Record::from_iter(BAM_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+
+                if !sink(Value::record(records_inner, call.head)) {
+                    break;
+                }
             }
-        }
-        temp_records
-    })) {
-        Ok(records) => value_records = records,
-        Err(_) => {
-            // If reading records fails (e.g., missing reference), return empty records with a note
-            return Ok(Value::record(
-                record! {
-                    "header" => header_nuon,
-                    "body" => Value::list(vec![], call.head),
-                    "note" => call.head.with_string("CRAM file may require a reference sequence for full parsing")
-                },
-                call.head,
-            ));
-        }
+
+            Ok(())
+        };
+
+        decode_records(decode, hint)
+    })
+}
+
+/// Query a coordinate sorted CRAM for the alignments overlapping `region`,
+/// using its `.crai` index so only the overlapping containers and slices are
+/// decoded.
+pub fn query_cram_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    path: PathBuf,
+    region: &str,
+    reference: Option<PathBuf>,
+) -> Result<ListStream, LabeledError> {
+    let region = parse_region(region)?;
+    let index_path = find_index(&path, &["crai"])?;
+
+    let index = cram::crai::fs::read(&index_path).map_err(|e| {
+        LabeledError::new(format!(
+            "Could not read the index {}. cause of failure: {}",
+            index_path.display(),
+            e
+        ))
+    })?;
+
+    let repository = match &reference {
+        Some(path) => reference_repository(path)?,
+        None => fasta::Repository::default(),
+    };
+
+    let mut reader = cram::io::indexed_reader::Builder::default()
+        .set_reference_sequence_repository(repository)
+        .set_index(index)
+        .build_from_path(&path)
+        .map_err(|e| {
+            LabeledError::new(format!(
+                "Could not open {}. cause of failure: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+    let header: sam::Header = reader.read_header().map_err(|e| {
+        LabeledError::new(format!(
+            "CRAM file header reading failed. cause of failure: {}",
+            e
+        ))
+    })?;
+
+    let options = RecordOptions::from_call(call)?;
+    let call = call.clone();
+
+    let hint = reference_hint(&reference);
+
+    Ok(stream_values(call.head, signals, move |sink| {
+        let decode = || -> Result<(), LabeledError> {
+            let query = reader.query(&header, &region).map_err(|e| {
+                LabeledError::new(format!(
+                    "Could not query region {}{}. cause of failure: {}",
+                    region, hint, e
+                ))
+            })?;
+
+            iterate_records(query, &call, &header, options, sink)
+        };
+
+        decode_records(decode, hint)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bio_format::bam::{from_bam_inner, query_bam_inner};
+    use crate::bio_format::index::index_inner;
+    use crate::bio_format::tests::{collect, fixture, fixture_copy, test_call};
+    use nu_protocol::Span;
+
+    /// The records in order of name and flags, with their tags in order of tag.
+    ///
+    /// CRAM doesn't keep the order of the tags, and NM and MD aren't stored
+    /// in it as they can be recomputed, so they are left out. Nor is the
+    /// mapping quality of unmapped reads.
+    fn by_name(rows: Vec<Value>) -> Vec<(String, Vec<(String, Value)>)> {
+        let mut rows: Vec<(String, Vec<(String, Value)>)> = rows
+            .into_iter()
+            .map(|row| {
+                let mut fields: Vec<(String, Value)> =
+                    row.into_record().unwrap().into_iter().collect();
+                let data = fields.iter().position(|(k, _)| k == "data").unwrap();
+                let (_, data) = fields.remove(data);
+                if fields[1].1.as_str().unwrap() == "0x0004" {
+                    fields.retain(|(k, _)| k != "mapping_quality");
+                }
+                let mut tags: Vec<(String, Value)> = data
+                    .into_record()
+                    .unwrap()
+                    .into_iter()
+                    .filter(|(tag, _)| tag != "NM" && tag != "MD")
+                    .collect();
+                tags.sort_by(|a, b| a.0.cmp(&b.0));
+                fields.extend(tags);
+                let key = |i: usize| fields[i].1.as_str().unwrap();
+                (format!("{} {}", key(0), key(1)), fields)
+            })
+            .collect();
+        rows.sort_by(|a, b| a.0.cmp(&b.0));
+        rows
+    }
+
+    fn body(output: PipelineData) -> Vec<Value> {
+        let value = output.into_value(Span::test_data()).unwrap();
+        value.get_data_by_key("body").unwrap().into_list().unwrap()
+    }
+
+    fn input(name: &str) -> PipelineData {
+        let bytes = std::fs::read(fixture(name)).unwrap();
+        PipelineData::Value(Value::test_binary(bytes), None)
+    }
+
+    #[test]
+    fn cram_decodes_against_its_reference() {
+        let call = test_call();
+        let reference = Some(fixture("drAilAlti1.fa"));
+        let cram = from_cram_inner(&call, &Signals::empty(), input("map.cram"), reference);
+        let bam = from_bam_inner(&call, &Signals::empty(), input("map.bam"));
+
+        let bam = body(bam.unwrap());
+        assert!(!bam.is_empty());
+        assert_eq!(by_name(body(cram.unwrap())), by_name(bam));
     }
 
-    Ok(Value::record(
-        record! {
-            "header" => header_nuon,
-            "body" => Value::list(value_records, call.head)
-        },
-        call.head,
-    ))
+    #[test]
+    fn cram_without_its_reference_is_an_error() {
+        let output = from_cram_inner(&test_call(), &Signals::empty(), input("map.cram"), None);
+        let err = output.unwrap_err();
+        assert!(err.msg.contains("--reference"), "{}", err.msg);
+    }
+
+    #[test]
+    fn cram_query_matches_the_bam() {
+        let path = fixture_copy("map.cram", "cram_query_matches_the_bam");
+        let reference = fixture("drAilAlti1.fa");
+        let call = test_call();
+        index_inner(&call, path.clone(), Some(reference.clone())).unwrap();
+
+        let region = "drAilAlti1:1000-2000";
+        let cram = collect(
+            query_cram_inner(
+                &call,
+                &Signals::empty(),
+                path.clone(),
+                region,
+                Some(reference),
+            )
+            .unwrap(),
+        );
+        let bam = collect(
+            query_bam_inner(&call, &Signals::empty(), fixture("map_sorted.bam"), region).unwrap(),
+        );
+
+        assert!(!bam.is_empty());
+        assert_eq!(by_name(cram), by_name(bam));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
 }
@@ -1,46 +1,49 @@
-use std::io::{BufRead, BufReader, Cursor};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
 
-use noodles_fasta::{
-    record::{Definition as FastaDefinition, Record as FastaRecord, Sequence},
-    io::Writer as FastaWriter,
-};
-use noodles_fastq::{
-    record::{Definition as FastqDefinition, Record as FastqRecord},
-    io::Writer as FastqWriter,
-};
 use noodles_bgzf as bgzf;
 use noodles_fasta as fasta;
+use noodles_fasta::record::{Definition as FastaDefinition, Record as FastaRecord, Sequence};
 use noodles_fastq as fastq;
+use noodles_fastq::{
+    io::Writer as FastqWriter,
+    record::{Definition as FastqDefinition, Record as FastqRecord},
+};
 use nu_plugin::EvaluatedCall;
 use nu_protocol::LabeledError;
-use nu_protocol::Value;
+use nu_protocol::{record, ListStream, PipelineData, Record, Signals, Value};
 
-use crate::bio_format::{Compression, SpanExt};
+use crate::bio_format::{
+    bgzf_compress, find_index, input_reader, parse_region, stream_values, Compression, InputReader,
+    Sink, SpanExt,
+};
 
 /// Compression status of a fastq reader.
-enum FastqReader<'a> {
-    Uncompressed(Box<fastq::io::Reader<&'a [u8]>>),
-    Compressed(Box<fastq::io::Reader<BufReader<bgzf::io::Reader<&'a [u8]>>>>),
+enum FastqReader {
+    Uncompressed(Box<fastq::io::Reader<BufReader<InputReader>>>),
+    Compressed(Box<fastq::io::Reader<BufReader<bgzf::io::Reader<InputReader>>>>),
 }
 
 /// Compression status of a fasta reader.
-enum FastaReader<'a> {
-    Uncompressed(Box<fasta::io::Reader<&'a [u8]>>),
-    Compressed(fasta::io::Reader<Box<bgzf::io::Reader<&'a [u8]>>>),
+enum FastaReader {
+    Uncompressed(Box<fasta::io::Reader<BufReader<InputReader>>>),
+    Compressed(fasta::io::Reader<Box<bgzf::io::Reader<InputReader>>>),
 }
 
 /// Iterate over the records of a reader that implements [`BufRead`].
 fn iterate_fastq_records<R: BufRead>(
     mut reader: fastq::io::Reader<R>,
     call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
+    sink: Sink,
     description: bool,
     quality_scores: bool,
     cols: Vec<String>,
 ) -> Result<(), LabeledError> {
     // iterate over the records.
     for record in reader.records() {
-        let r = record.map_err(|e| LabeledError::new(format!("Record reading failed. cause of failure: {}", e)))?;
+        let r = record.map_err(|e| {
+            LabeledError::new(format!("Record reading failed. cause of failure: {}", e))
+        })?;
 
         let mut vec_vals = Vec::new();
         vec_vals.push(call.head.with_string_from_utf8(r.name()));
@@ -56,34 +59,37 @@ fn iterate_fastq_records<R: BufRead>(
         vec_vals.push(call.head.with_string_from_utf8(r.sequence()));
 
         let mut tmp_record = nu_protocol::Record::new();
-        for (col, val) in cols.clone().iter().zip(vec_vals) {
+        for (col, val) in cols.iter().zip(vec_vals) {
             tmp_record.push(col, val);
         }
-        value_records.push(Value::record(tmp_record, call.head))
+
+        if !sink(Value::record(tmp_record, call.head)) {
+            break;
+        }
     }
 
     Ok(())
 }
 
+/// Parse a fastq file into a lazy stream of records.
 pub fn from_fastq_inner(
     call: &EvaluatedCall,
-    input: &Value,
+    signals: &Signals,
+    input: PipelineData,
     gz: Compression,
-) -> Result<Vec<Value>, LabeledError> {
+) -> Result<ListStream, LabeledError> {
     // parse description flag.
     let description = call.has_flag("description")?;
     let quality_scores = call.has_flag("quality-scores")?;
 
-    let bytes = match input {
-        Value::Binary { val, .. } => val.clone(),
-        Value::String { val, .. } => val.as_bytes().to_vec(),
-        _ => return Err(LabeledError::new("Input must be binary or string data")),
-    };
+    let input = input_reader(input, call.head)?;
 
     let reader = match gz {
-        Compression::Uncompressed => FastqReader::Uncompressed(Box::new(fastq::io::Reader::new(bytes.as_slice()))),
+        Compression::Uncompressed => {
+            FastqReader::Uncompressed(Box::new(fastq::io::Reader::new(BufReader::new(input))))
+        }
         Compression::Gzipped => {
-            let gz = bgzf::io::Reader::new(bytes.as_slice());
+            let gz = bgzf::io::Reader::new(input);
             FastqReader::Compressed(Box::new(fastq::io::Reader::new(BufReader::new(gz))))
         }
     };
@@ -108,40 +114,34 @@ pub fn from_fastq_inner(
         ],
     };
 
-    let mut value_records = Vec::new();
+    let call = call.clone();
 
-    match reader {
-        FastqReader::Uncompressed(u) => iterate_fastq_records(
-            *u,
-            call,
-            &mut value_records,
-            description,
-            quality_scores,
-            cols,
-        )?,
-        FastqReader::Compressed(c) => iterate_fastq_records(
-            *c,
-            call,
-            &mut value_records,
-            description,
-            quality_scores,
-            cols,
-        )?,
-    };
-
-    Ok(value_records)
+    Ok(stream_values(
+        call.head,
+        signals,
+        move |sink| match reader {
+            FastqReader::Uncompressed(u) => {
+                iterate_fastq_records(*u, &call, sink, description, quality_scores, cols)
+            }
+            FastqReader::Compressed(c) => {
+                iterate_fastq_records(*c, &call, sink, description, quality_scores, cols)
+            }
+        },
+    ))
 }
 
 fn iterate_fasta_records<R: BufRead>(
     mut reader: fasta::io::Reader<R>,
     call: &EvaluatedCall,
-    value_records: &mut Vec<Value>,
+    sink: Sink,
     description: bool,
     cols: Vec<String>,
 ) -> Result<(), LabeledError> {
     // iterate over the records
     for record in reader.records() {
-        let r = record.map_err(|e| LabeledError::new(format!("Record reading failed. cause of failure: {}", e)))?;
+        let r = record.map_err(|e| {
+            LabeledError::new(format!("Record reading failed. cause of failure: {}", e))
+        })?;
 
         let mut vec_vals = Vec::new();
 
@@ -154,33 +154,35 @@ fn iterate_fasta_records<R: BufRead>(
         vec_vals.push(call.head.with_string_from_utf8(r.sequence().as_ref()));
 
         let mut tmp_record = nu_protocol::Record::new();
-        for (col, val) in cols.clone().iter().zip(vec_vals) {
+        for (col, val) in cols.iter().zip(vec_vals) {
             tmp_record.push(col, val);
         }
-        value_records.push(Value::record(tmp_record, call.head))
+
+        if !sink(Value::record(tmp_record, call.head)) {
+            break;
+        }
     }
     Ok(())
 }
 
-/// Parse a fasta file into a nushell structure.
+/// Parse a fasta file into a lazy stream of records.
 pub fn from_fasta_inner(
     call: &EvaluatedCall,
-    input: &Value,
+    signals: &Signals,
+    input: PipelineData,
     gz: Compression,
-) -> Result<Vec<Value>, LabeledError> {
+) -> Result<ListStream, LabeledError> {
     // parse description flag.
     let description = call.has_flag("description")?;
 
-    let bytes = match input {
-        Value::Binary { val, .. } => val.clone(),
-        Value::String { val, .. } => val.as_bytes().to_vec(),
-        _ => return Err(LabeledError::new("Input must be binary or string data")),
-    };
+    let input = input_reader(input, call.head)?;
 
     let reader = match gz {
-        Compression::Uncompressed => FastaReader::Uncompressed(Box::new(fasta::io::Reader::new(bytes.as_slice()))),
+        Compression::Uncompressed => {
+            FastaReader::Uncompressed(Box::new(fasta::io::Reader::new(BufReader::new(input))))
+        }
         Compression::Gzipped => {
-            let gz = Box::new(bgzf::io::Reader::new(bytes.as_slice()));
+            let gz = Box::new(bgzf::io::Reader::new(input));
             FastaReader::Compressed(fasta::io::Reader::new(gz))
         }
     };
@@ -194,112 +196,490 @@ pub fn from_fasta_inner(
         ],
     };
 
-    let mut value_records = Vec::new();
-
-    match reader {
-        FastaReader::Uncompressed(u) => {
-            iterate_fasta_records(*u, call, &mut value_records, description, cols)?
-        }
-        FastaReader::Compressed(c) => {
-            iterate_fasta_records(c, call, &mut value_records, description, cols)?
-        }
-    };
+    let call = call.clone();
 
-    Ok(value_records)
+    Ok(stream_values(
+        call.head,
+        signals,
+        move |sink| match reader {
+            FastaReader::Uncompressed(u) => {
+                iterate_fasta_records(*u, &call, sink, description, cols)
+            }
+            FastaReader::Compressed(c) => iterate_fasta_records(c, &call, sink, description, cols),
+        },
+    ))
 }
 
-/// Go from a parsed nuon fasta structure to a string to stdout
+/// Fetch the subsequences of `regions` from an indexed fasta, using its
+/// `.fai`, and the `.gzi` too if the fasta is BGZF compressed.
 ///
-/// Note that this assumes that we are parsing fasta format specifically.
-pub fn nuon_to_fasta(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    let mut out = FastaWriter::new(Vec::new());
+/// Returns one row per region, with the region as the id.
+pub fn faidx_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    path: PathBuf,
+    regions: Vec<String>,
+) -> Result<ListStream, LabeledError> {
+    find_index(&path, &["fai"])?;
+    if matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("gz" | "bgz")
+    ) {
+        find_index(&path, &["gzi"])?;
+    }
 
-    if let Ok(list) = input.as_list() {
-        for el in list {
-            let inner = el.as_record()?;
-            let mut vals: Vec<Value> = inner.iter().map(|(_, v)| v.clone()).collect();
-            let last = vals.pop().unwrap();
-            let sequence = last.as_str()?;
+    let regions = regions
+        .into_iter()
+        .map(|region| parse_region(&region).map(|parsed| (region, parsed)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let call = call.clone();
+
+    Ok(stream_values(call.head, signals, move |sink| {
+        let mut reader = fasta::io::indexed_reader::Builder::default()
+            .build_from_path(&path)
+            .map_err(|e| {
+                LabeledError::new(format!(
+                    "Could not open the indexed fasta {}. cause of failure: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+
+        for (region, parsed) in regions {
+            // noodles clips an end past the sequence, which would not match the id.
+            let length = reader
+                .index()
+                .as_ref()
+                .iter()
+                .find(|record| record.name() == parsed.name())
+                .map(|record| record.length());
+            if let (Some(length), Some(end)) = (length, parsed.interval().end()) {
+                if usize::from(end) as u64 > length {
+                    return Err(LabeledError::new(format!(
+                        "Region {region} ends past the end of {}, which is {length} bases long",
+                        parsed.name()
+                    )));
+                }
+            }
 
-            let id = vals.get(0).map(|e| e.as_str().unwrap());
-            let description = vals.get(1).map(|e| e.as_str().unwrap());
+            let record = reader.query(&parsed).map_err(|e| {
+                LabeledError::new(format!(
+                    "Could not fetch region {}. cause of failure: {}",
+                    region, e
+                ))
+            })?;
 
-            let fa_def = FastaDefinition::new(id.unwrap_or("".into()), description.map(|s| s.as_bytes().into()));
-            let fa_seq = Sequence::from(sequence.as_bytes().to_vec());
+            let row = record! {
+                "id" => call.head.with_string(region),
+                "sequence" => call.head.with_string_from_utf8(record.sequence().as_ref()),
+            };
 
-            out.write_record(&FastaRecord::new(fa_def.clone(), fa_seq))
-                .map_err(|err| LabeledError::new(format!("Error in writing record ({}) to fasta: {}", fa_def, err)))?;
+            if !sink(Value::record(row, call.head)) {
+                break;
+            }
+        }
+
+        Ok(())
+    }))
+}
+
+/// The written records as a string, or as BGZF compressed binary.
+fn written_value(
+    call: &EvaluatedCall,
+    bytes: Vec<u8>,
+    gz: Compression,
+) -> Result<Value, LabeledError> {
+    match gz {
+        Compression::Uncompressed => {
+            let out_final = String::from_utf8(bytes).map_err(|err| {
+                LabeledError::new(format!("Can't format bytes as UTF-8: {}", err))
+            })?;
+            Ok(Value::string(out_final, call.head))
         }
+        Compression::Gzipped => Ok(Value::binary(bgzf_compress(&bytes)?, call.head)),
     }
+}
 
-    let bytes = out.get_ref();
-    let out_final = String::from_utf8(bytes.clone()).map_err(|err| LabeledError::new(format!("Can't format bytes as UTF-8: {}", err)))?;
+/// How fasta records should be written.
+pub struct FastaWriteOptions {
+    /// Bases per sequence line, 0 for a single line.
+    pub line_width: usize,
+    /// Builds the definition line from the columns, e.g. `{id} len={length}`.
+    pub header_template: Option<String>,
+}
 
-    Ok(Value::string(out_final, call.head))
+impl FastaWriteOptions {
+    /// Read the options from the flags passed to the command.
+    pub fn from_call(call: &EvaluatedCall) -> Result<Self, LabeledError> {
+        let line_width = match call.get_flag::<i64>("line-width")? {
+            Some(n) if n < 0 => {
+                return Err(LabeledError::new(format!(
+                    "--line-width must be 0 or more, got {n}"
+                )))
+            }
+            Some(n) => n as usize,
+            None => DEFAULT_LINE_WIDTH,
+        };
+
+        Ok(Self {
+            line_width,
+            header_template: call.get_flag::<String>("header-template")?,
+        })
+    }
 }
 
-pub fn nuon_to_fastq(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
-    let mut out = FastqWriter::new(Vec::new());
+/// The line width noodles wraps fasta sequences at.
+const DEFAULT_LINE_WIDTH: usize = 80;
+
+/// Fill in the `{column}` placeholders of a header template from a row.
+/// `{length}` is the length of the sequence, unless the row has that column.
+fn fill_header_template(
+    template: &str,
+    row: &Record,
+    sequence: &str,
+    i: usize,
+) -> Result<String, LabeledError> {
+    let mut header = String::new();
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        header.push_str(&rest[..open]);
+
+        let close = rest[open..].find('}').ok_or_else(|| {
+            LabeledError::new(format!("Unclosed {{ in the header template {template}"))
+        })?;
+        let column = &rest[open + 1..open + close];
+
+        let value = match (row.get(column), column) {
+            (Some(value), _) => value.coerce_string()?,
+            (None, "length") => sequence.len().to_string(),
+            (None, _) => {
+                return Err(LabeledError::new(format!(
+                    "Row {i} has no {column} column for the header template"
+                )))
+            }
+        };
+        header.push_str(&value);
+
+        rest = &rest[open + close + 1..];
+    }
+    header.push_str(rest);
+
+    Ok(header)
+}
+
+/// Get a string column of a row by name.
+fn string_column<'a>(
+    row: &'a Record,
+    column: &str,
+    i: usize,
+) -> Result<Option<&'a str>, LabeledError> {
+    match row.get(column) {
+        None | Some(Value::Nothing { .. }) => Ok(None),
+        Some(Value::String { val, .. }) => Ok(Some(val)),
+        Some(other) => Err(LabeledError::new(format!(
+            "Row {i} has a {} in the {column} column, expected a string",
+            other.get_type()
+        ))),
+    }
+}
+
+/// Go from a parsed nuon fasta structure to a string to stdout
+///
+/// The `id`, `description` (optional) and `sequence` columns are looked up by
+/// name, so any other columns are ignored, or can be used in the header
+/// template.
+pub fn nuon_to_fasta(
+    call: &EvaluatedCall,
+    input: &Value,
+    gz: Compression,
+) -> Result<Value, LabeledError> {
+    let options = FastaWriteOptions::from_call(call)?;
 
-    if let Ok(list) = input.as_list() {
-        // we need to check the columns
-        let first = list.first();
-        let (description, quality) = match first {
-            Some(e) => {
-                let first_inner = e.as_record()?;
-                let cols: Vec<String> = first_inner.iter().map(|(k, _)| k.clone()).collect();
-                (
-                    cols.contains(&String::from("description")),
-                    cols.contains(&String::from("quality_scores")),
-                )
+    let line_base_count = match options.line_width {
+        0 => usize::MAX,
+        n => n,
+    };
+    let mut out = fasta::io::writer::Builder::default()
+        .set_line_base_count(line_base_count)
+        .build_from_writer(Vec::new());
+
+    for (i, el) in input.as_list()?.iter().enumerate() {
+        let inner = el.as_record()?;
+        let row_error = |err: LabeledError| err.with_label("in this row", el.span());
+
+        let sequence = string_column(inner, "sequence", i)
+            .map_err(row_error)?
+            .ok_or_else(|| {
+                row_error(LabeledError::new(format!("Row {i} has no sequence column")))
+            })?;
+
+        let fa_def = match &options.header_template {
+            Some(template) => {
+                let header =
+                    fill_header_template(template, inner, sequence, i).map_err(row_error)?;
+                match header.split_once(char::is_whitespace) {
+                    Some((id, description)) => {
+                        FastaDefinition::new(id, Some(description.as_bytes().into()))
+                    }
+                    None => FastaDefinition::new(header.as_str(), None),
+                }
             }
             None => {
-                // what's the error?
-                return Err(LabeledError::new("No value: There was no first value to call `to fastq` on"));
+                let id = string_column(inner, "id", i)
+                    .map_err(row_error)?
+                    .ok_or_else(|| {
+                        row_error(LabeledError::new(format!("Row {i} has no id column")))
+                    })?;
+                let description = string_column(inner, "description", i).map_err(row_error)?;
+                FastaDefinition::new(id, description.map(|s| s.as_bytes().into()))
             }
         };
+        let fa_seq = Sequence::from(sequence.as_bytes().to_vec());
+
+        out.write_record(&FastaRecord::new(fa_def.clone(), fa_seq))
+            .map_err(|err| {
+                LabeledError::new(format!(
+                    "Error in writing record ({}) to fasta: {}",
+                    fa_def, err
+                ))
+            })?;
+    }
 
-        // if we don't have quality scores no point going further.
-        if !quality {
-            return Err(LabeledError::new("No quality scores: Consider using `to fasta` if you don't have any quality scores, or pass the -q option on a fastq"));
-        }
+    written_value(call, out.get_ref().clone(), gz)
+}
 
-        for el in list {
-            let inner = el.as_record()?;
-            // we need to check the columns.
-            let mut vals: Vec<Value> = inner.iter().map(|(_, v)| v.clone()).collect();
-            let last = vals.pop().unwrap();
-            let sequence = last.as_str()?;
-
-            let id = vals.get(0).map(|e| e.as_str().unwrap());
-
-            let (d, q) = match (description, quality) {
-                (true, true) => {
-                    // we got both
-                    let d = vals.get(1).map(|e| e.as_str().unwrap());
-                    let q = vals.get(2).map(|e| e.as_str().unwrap());
-                    (d, q)
-                }
-                (false, true) => {
-                    let q = vals.get(1).map(|e| e.as_str().unwrap());
-                    (None, q)
-                }
-                _ => unreachable!(),
-            };
+/// Read the quality scores of a row, either a Phred+33 string or a list of
+/// Phred ints.
+fn quality_scores_column(row: &Value, i: usize) -> Result<Vec<u8>, LabeledError> {
+    let row_error = |msg: String| LabeledError::new(msg).with_label("in this row", row.span());
+
+    match row.as_record()?.get("quality_scores") {
+        Some(Value::String { val, .. }) => Ok(val.as_bytes().to_vec()),
+        Some(Value::List { vals, .. }) => vals
+            .iter()
+            .map(|q| match q.as_int() {
+                Ok(q @ 0..=93) => Ok(q as u8 + 33),
+                _ => Err(row_error(format!(
+                    "Row {i} has a quality score that is not a Phred int from 0 to 93"
+                ))),
+            })
+            .collect(),
+        Some(other) => Err(row_error(format!(
+            "Row {i} has a {} in the quality_scores column, expected a string or a list of ints",
+            other.get_type()
+        ))),
+        None => Err(row_error(format!(
+            "Row {i} has no quality_scores column: consider using `to fasta` if you don't have any quality scores"
+        ))),
+    }
+}
+
+/// Go from a parsed nuon fastq structure to a string.
+///
+/// The `id`, `description` (optional), `sequence` and `quality_scores`
+/// columns are looked up by name.
+pub fn nuon_to_fastq(
+    call: &EvaluatedCall,
+    input: &Value,
+    gz: Compression,
+) -> Result<Value, LabeledError> {
+    let mut out = FastqWriter::new(Vec::new());
+
+    for (i, el) in input.as_list()?.iter().enumerate() {
+        let inner = el.as_record()?;
+        let row_error = |err: LabeledError| err.with_label("in this row", el.span());
+
+        let id = string_column(inner, "id", i)
+            .map_err(row_error)?
+            .ok_or_else(|| row_error(LabeledError::new(format!("Row {i} has no id column"))))?;
+        let description = string_column(inner, "description", i).map_err(row_error)?;
+        let sequence = string_column(inner, "sequence", i)
+            .map_err(row_error)?
+            .ok_or_else(|| {
+                row_error(LabeledError::new(format!("Row {i} has no sequence column")))
+            })?;
+        let quality_scores = quality_scores_column(el, i)?;
+
+        if sequence.len() != quality_scores.len() {
+            return Err(row_error(LabeledError::new(format!(
+                "Row {i} ({id}) has {} bases but {} quality scores",
+                sequence.len(),
+                quality_scores.len()
+            ))));
+        }
 
-            let fq_def = FastqDefinition::new(id.unwrap_or("".into()), d.unwrap_or("".into()));
+        let fq_def = FastqDefinition::new(id, description.unwrap_or(""));
 
-            out.write_record(&FastqRecord::new(
-                fq_def.clone(),
-                sequence.as_bytes(),
-                q.unwrap_or("".into()).as_bytes(),
+        out.write_record(&FastqRecord::new(
+            fq_def.clone(),
+            sequence.as_bytes(),
+            quality_scores,
+        ))
+        .map_err(|err| {
+            LabeledError::new(format!(
+                "Error in writing record ({:?}) to fastq: {}",
+                fq_def, err
             ))
-            .map_err(|err| LabeledError::new(format!("Error in writing record ({:?}) to fastq: {}", fq_def, err)))?;
+        })?;
+    }
+
+    written_value(call, out.get_ref().clone(), gz)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bio_format::tests::{call_with_switches, collect, fixture, test_call, try_collect};
+    use nu_protocol::{Span, Spanned};
+
+    fn input(bytes: Vec<u8>) -> PipelineData {
+        PipelineData::Value(Value::test_binary(bytes), None)
+    }
+
+    fn fasta_rows(call: &EvaluatedCall, bytes: Vec<u8>, gz: Compression) -> Vec<Value> {
+        collect(from_fasta_inner(call, &Signals::empty(), input(bytes), gz).unwrap())
+    }
+
+    fn fastq_rows(call: &EvaluatedCall, bytes: Vec<u8>, gz: Compression) -> Vec<Value> {
+        collect(from_fastq_inner(call, &Signals::empty(), input(bytes), gz).unwrap())
+    }
+
+    #[test]
+    fn fasta_gz_round_trip() {
+        let call = call_with_switches(&["description"]);
+        let rows = fasta_rows(
+            &call,
+            std::fs::read(fixture("fasta_to_map.fa")).unwrap(),
+            Compression::Uncompressed,
+        );
+
+        let written = nuon_to_fasta(&call, &Value::test_list(rows.clone()), Compression::Gzipped)
+            .unwrap()
+            .as_binary()
+            .unwrap()
+            .to_vec();
+        assert_eq!(written[..4], [0x1f, 0x8b, 0x08, 0x04]);
+        assert_eq!(fasta_rows(&call, written, Compression::Gzipped), rows);
+    }
+
+    #[test]
+    fn fastq_gz_round_trip() {
+        let call = call_with_switches(&["description", "quality-scores"]);
+        let rows = fastq_rows(
+            &call,
+            std::fs::read(fixture("test.fq")).unwrap(),
+            Compression::Uncompressed,
+        );
+
+        let written = nuon_to_fastq(&call, &Value::test_list(rows.clone()), Compression::Gzipped)
+            .unwrap()
+            .as_binary()
+            .unwrap()
+            .to_vec();
+        assert_eq!(written[..4], [0x1f, 0x8b, 0x08, 0x04]);
+        assert_eq!(fastq_rows(&call, written, Compression::Gzipped), rows);
+    }
+
+    #[test]
+    fn line_width_and_header_template() {
+        let mut call = test_call();
+        for (flag, value) in [
+            ("line-width", Value::test_int(4)),
+            ("header-template", Value::test_string("{id} len={length}")),
+        ] {
+            let name = Spanned {
+                item: flag.to_string(),
+                span: call.head,
+            };
+            call.named.push((name, Some(value)));
         }
+
+        let rows = Value::test_list(vec![Value::test_record(record! {
+            "id" => Value::test_string("seq1"),
+            "sequence" => Value::test_string("ACGTACGTAC"),
+        })]);
+        let fasta = nuon_to_fasta(&call, &rows, Compression::Uncompressed).unwrap();
+        assert_eq!(fasta.as_str().unwrap(), ">seq1 len=10\nACGT\nACGT\nAC\n");
     }
 
-    let bytes = out.get_ref();
-    let out_final = String::from_utf8(bytes.clone()).map_err(|err| LabeledError::new(format!("Can't format bytes as UTF-8: {}", err)))?;
+    #[test]
+    fn errors_point_at_the_row() {
+        let span = Span::new(5, 10);
+        let rows = Value::test_list(vec![Value::record(
+            record! { "sequence" => Value::test_string("ACGT") },
+            span,
+        )]);
 
-    Ok(Value::string(out_final, call.head))
+        let err = nuon_to_fasta(&test_call(), &rows, Compression::Uncompressed).unwrap_err();
+        assert_eq!(err.labels[0].span, span);
+    }
+
+    #[test]
+    fn fastq_columns_are_found_by_name() {
+        let rows = Value::test_list(vec![
+            Value::test_record(record! {
+                "sequence" => Value::test_string("ACGT"),
+                "extra" => Value::test_int(1),
+                "quality_scores" => Value::test_string("II#I"),
+                "id" => Value::test_string("read1"),
+            }),
+            Value::test_record(record! {
+                "id" => Value::test_string("read2"),
+                "description" => Value::test_string("sample=a"),
+                "sequence" => Value::test_string("GG"),
+                "quality_scores" => Value::test_list(vec![Value::test_int(40), Value::test_int(2)]),
+            }),
+        ]);
+
+        let fastq = nuon_to_fastq(&test_call(), &rows, Compression::Uncompressed).unwrap();
+        assert_eq!(
+            fastq.as_str().unwrap(),
+            "@read1\nACGT\n+\nII#I\n@read2 sample=a\nGG\n+\nI#\n"
+        );
+    }
+
+    #[test]
+    fn fastq_quality_length_must_match() {
+        let rows = Value::test_list(vec![Value::test_record(record! {
+            "id" => Value::test_string("read1"),
+            "sequence" => Value::test_string("ACGT"),
+            "quality_scores" => Value::test_string("II"),
+        })]);
+
+        assert!(nuon_to_fastq(&test_call(), &rows, Compression::Uncompressed).is_err());
+    }
+
+    fn faidx(regions: &[&str]) -> Result<Vec<Value>, LabeledError> {
+        let regions = regions.iter().map(|r| r.to_string()).collect();
+        let stream = faidx_inner(
+            &test_call(),
+            &Signals::empty(),
+            fixture("drAilAlti1.fa"),
+            regions,
+        )?;
+        try_collect(stream)
+    }
+
+    #[test]
+    fn faidx_fetches_the_region() {
+        let rows = faidx(&["drAilAlti1:1-10", "drAilAlti1:19700-19709"]).unwrap();
+        let field = |row: &Value, key: &str| {
+            row.get_data_by_key(key)
+                .unwrap()
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(field(&rows[0], "id"), "drAilAlti1:1-10");
+        assert_eq!(field(&rows[0], "sequence").len(), 10);
+        assert_eq!(field(&rows[1], "sequence").len(), 10);
+    }
+
+    #[test]
+    fn faidx_end_past_the_sequence_is_an_error() {
+        assert!(faidx(&["drAilAlti1:19700-20000"]).is_err());
+    }
 }
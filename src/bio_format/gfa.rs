@@ -8,26 +8,22 @@ use gfa::{
 };
 use nu_plugin::EvaluatedCall;
 use nu_protocol::LabeledError;
-use nu_protocol::{record, Value};
-use std::io::{BufRead, BufReader, Cursor};
+use nu_protocol::{record, PipelineData, Record, Value};
+use std::io::{BufRead, BufReader};
 
-use super::{Compression, SpanExt};
+use super::{input_reader, Compression, InputReader, SpanExt};
 use noodles_bgzf as bgzf;
 
-/// Compression status of a VCF reader.
-enum GFAReader<'a> {
-    Uncompressed(bstr::io::ByteLines<std::io::BufReader<&'a [u8]>>),
-    Compressed(bstr::io::ByteLines<bgzf::io::Reader<std::io::BufReader<&'a [u8]>>>),
+/// Compression status of a GFA reader.
+enum GFAReader {
+    Uncompressed(bstr::io::ByteLines<std::io::BufReader<InputReader>>),
+    Compressed(bstr::io::ByteLines<bgzf::io::Reader<std::io::BufReader<InputReader>>>),
 }
 
 /// We do a lot of string conversion in this module,
 /// so make a string from utf8 function with nice error
 /// handling.
-fn string_from_utf8(
-    inner: Vec<u8>,
-    call: &EvaluatedCall,
-    context: &str,
-) -> Result<String, LabeledError> {
+fn string_from_utf8(inner: Vec<u8>, context: &str) -> Result<String, LabeledError> {
     String::from_utf8(inner).map_err(|e| LabeledError::new(format!("{}: {}", context, e)))
 }
 
@@ -39,7 +35,7 @@ fn parse_optfieldval(opt_field: OptField, call: &EvaluatedCall) -> Result<Value,
 
     // TAG:TYPE:VALUE
     let tag_type_value = |typ: String, value: String, b: String| -> Result<Value, LabeledError> {
-        let tag_string = string_from_utf8(tag.to_vec(), call, "tag is malformed")?;
+        let tag_string = string_from_utf8(tag.to_vec(), "tag is malformed")?;
 
         Ok(call
             .head
@@ -50,7 +46,7 @@ fn parse_optfieldval(opt_field: OptField, call: &EvaluatedCall) -> Result<Value,
         // A (character)
         OptFieldVal::A(a) => tag_type_value(
             "A".into(),
-            string_from_utf8(vec![a], call, "'A' value malformed")?,
+            string_from_utf8(vec![a], "'A' value malformed")?,
             "".into(),
         ),
         // i (integer)
@@ -60,22 +56,20 @@ fn parse_optfieldval(opt_field: OptField, call: &EvaluatedCall) -> Result<Value,
         // Z (string)
         OptFieldVal::Z(z) => tag_type_value(
             String::from("Z"),
-            string_from_utf8(z, call, "Z value malformed")?,
+            string_from_utf8(z, "Z value malformed")?,
             "".into(),
         ),
         // J is JSON
         // just handle this as a string
         OptFieldVal::J(j) => tag_type_value(
             String::from("J"),
-            string_from_utf8(j, call, "J JSON value malformed")?,
+            string_from_utf8(j, "J JSON value malformed")?,
             "".into(),
         ),
-        // H (hexadecimal array)
+        // H (hexadecimal array), parsed one digit per element
         OptFieldVal::H(h) => tag_type_value(
-            String::from("Z"),
-            h.iter()
-                .map(|e| format!("{:#05x}", e))
-                .fold(String::new(), |a, b| a + &b + ","),
+            String::from("H"),
+            h.iter().map(|e| format!("{:X}", e)).collect(),
             "".into(),
         ),
         // B (general array) - here it's split
@@ -83,15 +77,15 @@ fn parse_optfieldval(opt_field: OptField, call: &EvaluatedCall) -> Result<Value,
             String::from("B"),
             bi.iter()
                 .map(|e| e.to_string())
-                .fold(String::new(), |a, b| a + &b + ","),
-            "i:".into(),
+                .fold(String::new(), |a, b| a + "," + &b),
+            "i".into(),
         ),
         OptFieldVal::BFloat(bf) => tag_type_value(
             String::from("B"),
             bf.iter()
                 .map(|e| e.to_string())
-                .fold(String::new(), |a, b| a + &b + ","),
-            "f:".into(),
+                .fold(String::new(), |a, b| a + "," + &b),
+            "f".into(),
         ),
     }
 }
@@ -109,7 +103,12 @@ fn lines_to_nuon<R: BufRead>(
     call: &EvaluatedCall,
 ) -> Result<(), LabeledError> {
     for line in gfa_reader {
-        let line = line.map_err(|e| LabeledError::new(format!("Could not read a line in the GFA. cause of failure: {}", e)))?;
+        let line = line.map_err(|e| {
+            LabeledError::new(format!(
+                "Could not read a line in the GFA. cause of failure: {}",
+                e
+            ))
+        })?;
         // if this not added then
         if line.is_empty() {
             continue;
@@ -134,13 +133,13 @@ fn lines_to_nuon<R: BufRead>(
                         ))
                     }
                     Segment(s) => {
-                        let name = string_from_utf8(s.name, call, "segment name malformed");
+                        let name = string_from_utf8(s.name, "segment name malformed");
                         let opts: Result<Vec<Value>, _> = s
                             .optional
                             .iter()
                             .map(|e| parse_optfieldval(e.clone(), call))
                             .collect();
-                        let seq = string_from_utf8(s.sequence, call, "segment sequence malformed")?;
+                        let seq = string_from_utf8(s.sequence, "segment sequence malformed")?;
 
                         segments_nuon.push(Value::record(
                             record! {
@@ -152,10 +151,9 @@ fn lines_to_nuon<R: BufRead>(
                         ))
                     }
                     Link(l) => {
-                        let fs = string_from_utf8(l.from_segment, call, "from segment malformed")?;
-                        let ts = string_from_utf8(l.to_segment, call, "to segment malformed")?;
-                        let overlap =
-                            string_from_utf8(l.overlap, call, "overlap (CIGAR) malformed")?;
+                        let fs = string_from_utf8(l.from_segment, "from segment malformed")?;
+                        let ts = string_from_utf8(l.to_segment, "to segment malformed")?;
+                        let overlap = string_from_utf8(l.overlap, "overlap (CIGAR) malformed")?;
                         let opts: Result<Vec<Value>, _> = l
                             .optional
                             .iter()
@@ -176,11 +174,10 @@ fn lines_to_nuon<R: BufRead>(
                     }
                     Containment(c) => {
                         let containment_name =
-                            string_from_utf8(c.contained_name, call, "containment name malformed");
+                            string_from_utf8(c.contained_name, "containment name malformed");
                         let container_name =
-                            string_from_utf8(c.container_name, call, "container name malformed");
-                        let overlap =
-                            string_from_utf8(c.overlap, call, "overlap (CIGAR) malformed");
+                            string_from_utf8(c.container_name, "container name malformed");
+                        let overlap = string_from_utf8(c.overlap, "overlap (CIGAR) malformed");
                         let position = c.pos;
                         let opts: Result<Vec<Value>, _> = c
                             .optional
@@ -202,12 +199,9 @@ fn lines_to_nuon<R: BufRead>(
                         ))
                     }
                     Path(p) => {
-                        let path_name = string_from_utf8(p.path_name, call, "malformed path name");
-                        let segment_names = string_from_utf8(
-                            p.segment_names,
-                            call,
-                            "segment names in path malformed",
-                        )?;
+                        let path_name = string_from_utf8(p.path_name, "malformed path name");
+                        let segment_names =
+                            string_from_utf8(p.segment_names, "segment names in path malformed")?;
                         let overlaps: Vec<Value> = p
                             .overlaps
                             .iter()
@@ -234,30 +228,29 @@ fn lines_to_nuon<R: BufRead>(
             // I don't have access to the .tolerance field...
             // Err(err) if err.can_safely_continue(&parser.tolerance) => (),
             Err(e) => {
-                return Err(LabeledError::new(format!("Could not stream input as binary. cause of failure: {}", e)))
+                return Err(LabeledError::new(format!(
+                    "Could not stream input as binary. cause of failure: {}",
+                    e
+                )))
             }
         };
     }
     Ok(())
 }
 
+/// Parse a GFA into a record of its header, segments, links, containments and
+/// paths. Each section is collected in full, so this is not streamed.
 pub fn from_gfa_inner(
     call: &EvaluatedCall,
-    input: &Value,
+    input: PipelineData,
     gz: Compression,
 ) -> Result<Value, LabeledError> {
     let parser: GFAParser<Vec<u8>, Vec<OptField>> = GFAParser::new();
 
-    let bytes = match input {
-        Value::Binary { val, .. } => val.clone(),
-        Value::String { val, .. } => val.as_bytes().to_vec(),
-        _ => return Err(LabeledError::new("Input must be binary or string data")),
-    };
-
-    let reader = BufReader::new(bytes.as_slice());
+    let reader = BufReader::new(input_reader(input, call.head)?);
     let lines = match gz {
         Compression::Uncompressed => GFAReader::Uncompressed(reader.byte_lines()),
-        Compression::Gzipped => GFAReader::Compressed(bgzf::io::Reader::new(BufReader::new(bytes.as_slice())).byte_lines()),
+        Compression::Gzipped => GFAReader::Compressed(bgzf::io::Reader::new(reader).byte_lines()),
     };
 
     let mut header_nuon = Vec::new();
@@ -300,3 +293,188 @@ pub fn from_gfa_inner(
         call.head,
     ))
 }
+
+/// Format one optional field. These are either the `TAG:TYPE:VALUE` strings
+/// made by `from gfa`, or a value which is given a type from its own.
+fn optional_field_to_gfa(tag: Option<&str>, value: &Value) -> Result<String, LabeledError> {
+    let tag_type_value = |typ: &str, value: String| match tag {
+        Some(tag) => Ok(format!("{tag}:{typ}:{value}")),
+        None => Err(LabeledError::new(format!(
+            "Optional field {value} needs a tag, as TAG:TYPE:VALUE"
+        ))),
+    };
+
+    match value {
+        Value::String { val, .. } if tag.is_none() => {
+            match val.splitn(3, ':').collect::<Vec<_>>().as_slice() {
+                [t, ty, _] if t.len() == 2 && ty.len() == 1 => Ok(val.clone()),
+                _ => Err(LabeledError::new(format!(
+                    "Optional field {val} is not in TAG:TYPE:VALUE form"
+                ))),
+            }
+        }
+        Value::String { val, .. } => tag_type_value("Z", val.clone()),
+        Value::Int { val, .. } => tag_type_value("i", val.to_string()),
+        Value::Float { val, .. } => tag_type_value("f", val.to_string()),
+        Value::List { vals, .. } => {
+            let subtype = match vals.iter().all(|v| matches!(v, Value::Int { .. })) {
+                true => "i",
+                false => "f",
+            };
+            let values = vals
+                .iter()
+                .map(|v| v.coerce_string().map_err(LabeledError::from))
+                .collect::<Result<Vec<_>, _>>()?;
+            tag_type_value("B", format!("{subtype},{}", values.join(",")))
+        }
+        other => Err(LabeledError::new(format!(
+            "Can't write a {} as a GFA optional field",
+            other.get_type()
+        ))),
+    }
+}
+
+/// Format the `optional_fields` of a line, either a list of `TAG:TYPE:VALUE`
+/// strings, or a record keyed by tag.
+fn optional_fields_to_gfa(line: &Record) -> Result<Vec<String>, LabeledError> {
+    match line.get("optional_fields") {
+        Some(Value::List { vals, .. }) => vals
+            .iter()
+            .map(|v| optional_field_to_gfa(None, v))
+            .collect(),
+        Some(Value::Record { val, .. }) => val
+            .iter()
+            .map(|(tag, v)| optional_field_to_gfa(Some(tag), v))
+            .collect(),
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Format a line of the given record type, from its columns in order.
+fn line_to_gfa(
+    record_type: &str,
+    line: &Value,
+    columns: &[&str],
+    i: usize,
+) -> Result<String, LabeledError> {
+    let line = line.as_record()?;
+
+    let mut fields = vec![record_type.to_string()];
+    for column in columns {
+        let field = match line.get(*column) {
+            // the paths overlaps are a list.
+            Some(Value::List { vals, .. }) if vals.is_empty() => "*".to_string(),
+            Some(Value::List { vals, .. }) => vals
+                .iter()
+                .map(|v| v.coerce_string().map_err(LabeledError::from))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(","),
+            Some(Value::String { val, .. }) if val.is_empty() => "*".to_string(),
+            Some(value) => value.coerce_string()?,
+            None => {
+                return Err(LabeledError::new(format!(
+                    "{record_type} line {i} is missing the {column} column"
+                )))
+            }
+        };
+        fields.push(field);
+    }
+    fields.extend(optional_fields_to_gfa(line)?);
+
+    Ok(fields.join("\t") + "\n")
+}
+
+/// Go from the record made by `from gfa` to GFA 1.0 text.
+pub fn nuon_to_gfa(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let input = input.as_record()?;
+
+    let mut out = String::new();
+
+    // the header may be the "No header" placeholder.
+    match input.get("header") {
+        Some(Value::Record { val, .. }) => {
+            let mut fields = vec!["H".to_string()];
+            if let Some(Value::String { val: version, .. }) = val.get("version") {
+                if version != "No version specified" {
+                    fields.push(format!("VN:Z:{version}"));
+                }
+            }
+            fields.extend(optional_fields_to_gfa(val)?);
+            out.push_str(&(fields.join("\t") + "\n"));
+        }
+        _ => out.push_str("H\tVN:Z:1.0\n"),
+    }
+
+    let lines = |key: &str| match input.get(key) {
+        Some(Value::List { vals, .. }) => vals.as_slice(),
+        _ => &[],
+    };
+
+    for (i, segment) in lines("segments").iter().enumerate() {
+        out.push_str(&line_to_gfa("S", segment, &["name", "sequence"], i)?);
+    }
+
+    for (i, link) in lines("links").iter().enumerate() {
+        let columns = [
+            "from_segment",
+            "from_orient",
+            "to_segment",
+            "to_orient",
+            "overlaps",
+        ];
+        out.push_str(&line_to_gfa("L", link, &columns, i)?);
+    }
+
+    for (i, containment) in lines("containments").iter().enumerate() {
+        let columns = [
+            "container_name",
+            "container_orient",
+            "containment_name",
+            "containment_orient",
+            "position",
+            "overlap",
+        ];
+        out.push_str(&line_to_gfa("C", containment, &columns, i)?);
+    }
+
+    for (i, path) in lines("paths").iter().enumerate() {
+        let columns = ["path_name", "segment_names", "overlaps"];
+        out.push_str(&line_to_gfa("P", path, &columns, i)?);
+    }
+
+    Ok(Value::string(out, call.head))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bio_format::tests::{fixture, test_call};
+
+    fn from_gfa(bytes: Vec<u8>, gz: Compression) -> Value {
+        let input = PipelineData::Value(Value::test_binary(bytes), None);
+        from_gfa_inner(&test_call(), input, gz).unwrap()
+    }
+
+    #[test]
+    fn hex_and_array_fields_round_trip() {
+        let text = "H\tVN:Z:1.0
+S\t1\tACGT\tHX:H:1AE3\tXB:B:i,1,-2\tXF:B:f,0.5,1.5\tLN:i:4
+S\t2\tGG\tXA:A:c
+L\t1\t+\t2\t-\t0M
+";
+        let gfa = from_gfa(text.as_bytes().to_vec(), Compression::Uncompressed);
+        let written = nuon_to_gfa(&test_call(), &gfa).unwrap();
+        assert_eq!(written.as_str().unwrap(), text);
+    }
+
+    #[test]
+    fn gfa_round_trip() {
+        let gfa = from_gfa(
+            std::fs::read(fixture("map.gfa")).unwrap(),
+            Compression::Uncompressed,
+        );
+        let written = nuon_to_gfa(&test_call(), &gfa).unwrap();
+        let bytes = written.as_str().unwrap().as_bytes().to_vec();
+        assert_eq!(from_gfa(bytes, Compression::Uncompressed), gfa);
+    }
+}
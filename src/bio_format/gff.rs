@@ -1,13 +1,16 @@
 /// The GFF format
-use noodles_gff as gff;
+use noodles_fasta as fasta;
 use nu_plugin::EvaluatedCall;
 use nu_protocol::LabeledError;
-use nu_protocol::{Record, Value};
+use nu_protocol::{record, ListStream, PipelineData, Record, Signals, Value};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
 
-use super::SpanExt;
+use super::index::{query_tabix_lines, read_tabix_index, TabixFormat};
+use super::{collect_values, input_reader, parse_region, stream_values, Sink, SpanExt};
 
 /// The GFF3 headers
-const GFF_COLUMNS: &[&str] = &[
+pub const GFF_COLUMNS: &[&str] = &[
     "ref_seq_name",
     "source",
     "ty",
@@ -19,36 +22,575 @@ const GFF_COLUMNS: &[&str] = &[
     "attributes",
 ];
 
-/// Parse a fasta file into a nushell structure.
-pub fn from_gff_inner(call: &EvaluatedCall, input: &Value) -> Result<Vec<Value>, LabeledError> {
-    // match on file type
-    let stream = match input {
-        Value::Binary { val, .. } => val,
-        Value::String { val, .. } => val.as_bytes(),
-        _ => return Err(LabeledError::new("Input must be binary or string data")),
+/// Decode the `%XX` escapes GFF3 uses for reserved characters.
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(b) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                decoded.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Parse the ninth column into a record. Attributes with more than one
+/// value (e.g. `Parent=a,b`) become lists.
+fn parse_attributes(call: &EvaluatedCall, attributes: &str) -> Value {
+    let mut record = Record::new();
+
+    if attributes == "." {
+        return Value::record(record, call.head);
+    }
+
+    for attribute in attributes.split(';').filter(|e| !e.trim().is_empty()) {
+        let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+        let values: Vec<String> = value.split(',').map(percent_decode).collect();
+
+        let value = match values.as_slice() {
+            [single] => call.head.with_string(single),
+            _ => Value::list(
+                values.iter().map(|e| call.head.with_string(e)).collect(),
+                call.head,
+            ),
+        };
+
+        record.push(percent_decode(key.trim()), value);
+    }
+
+    Value::record(record, call.head)
+}
+
+/// Parse a GFF3 record line into a row of [`GFF_COLUMNS`].
+fn parse_record(
+    call: &EvaluatedCall,
+    line: &str,
+    line_number: usize,
+) -> Result<Value, LabeledError> {
+    let fields: Vec<&str> = line.split('\t').collect();
+
+    if fields.len() != GFF_COLUMNS.len() {
+        return Err(LabeledError::new(format!(
+            "Expected {} columns on line {line_number} of the GFF, found {}",
+            GFF_COLUMNS.len(),
+            fields.len()
+        )));
+    }
+
+    let position = |field: &str, column: &str| {
+        field.parse::<i64>().map_err(|e| {
+            LabeledError::new(format!(
+                "Could not parse {column} on line {line_number} of the GFF: {e}"
+            ))
+        })
     };
 
-    let mut reader = gff::io::Reader::new(stream);
+    let score = match fields[5] {
+        "." => Value::nothing(call.head),
+        s => Value::float(
+            s.parse::<f64>().map_err(|e| {
+                LabeledError::new(format!(
+                    "Could not parse score on line {line_number} of the GFF: {e}"
+                ))
+            })?,
+            call.head,
+        ),
+    };
+
+    let phase = match fields[7] {
+        "." => Value::nothing(call.head),
+        p => Value::int(position(p, "phase")?, call.head),
+    };
+
+    let values = vec![
+        call.head.with_string(percent_decode(fields[0])),
+        call.head.with_string(percent_decode(fields[1])),
+        call.head.with_string(percent_decode(fields[2])),
+        Value::int(position(fields[3], "start")?, call.head),
+        Value::int(position(fields[4], "end")?, call.head),
+        score,
+        call.head.with_string(fields[6]),
+        phase,
+        parse_attributes(call, fields[8]),
+    ];
+
+    Ok(Value::record(
+        Record::from_iter(GFF_COLUMNS.iter().map(|e| e.to_string()).zip(values)),
+        call.head,
+    ))
+}
+
+/// Parse the sequences after a `##FASTA` directive.
+fn parse_fasta_trailer(call: &EvaluatedCall, fasta: &[u8]) -> Result<Value, LabeledError> {
+    let mut reader = fasta::io::Reader::new(fasta);
+
+    let records = reader
+        .records()
+        .map(|record| {
+            let r = record.map_err(|e| {
+                LabeledError::new(format!("Could not read the GFF FASTA section: {}", e))
+            })?;
 
-    let mut value_records = Vec::new();
+            Ok(Value::record(
+                record! {
+                    "id" => call.head.with_string(String::from_utf8_lossy(r.name())),
+                    "sequence" => call.head.with_string_from_utf8(r.sequence().as_ref()),
+                },
+                call.head,
+            ))
+        })
+        .collect::<Result<Vec<_>, LabeledError>>()?;
 
-    // GFF API changed - temporarily disabled
-    /*for record in reader.records() {
-        let r = match record {
-            Ok(rec) => rec,
-            Err(e) => {
-                return Err(LabeledError::new(format!("Record reading failed. cause of failure: {}", e)))
+    Ok(Value::list(records, call.head))
+}
+
+/// Read a GFF3 file line by line, sending each feature to the sink.
+///
+/// Returns the header directives, and the raw `##FASTA` section.
+fn parse_gff<R: BufRead>(
+    call: &EvaluatedCall,
+    reader: R,
+    sink: Sink,
+) -> Result<(Value, Vec<u8>), LabeledError> {
+    let mut version = Value::nothing(call.head);
+    let mut sequence_regions = Record::new();
+    let mut other_directives = Vec::new();
+    let mut fasta = Vec::new();
+
+    let mut lines = reader.lines().enumerate();
+
+    while let Some((i, line)) = lines.next() {
+        let line_number = i + 1;
+        let line = line.map_err(|e| {
+            LabeledError::new(format!("Could not read line {line_number} of the GFF: {e}"))
+        })?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // everything from here on is fasta, and may start without the directive.
+        if line == "##FASTA" || line.starts_with('>') {
+            if line.starts_with('>') {
+                fasta.extend_from_slice(line.as_bytes());
+                fasta.push(b'\n');
             }
-        };
+            for (_, line) in lines.by_ref() {
+                let line = line.map_err(|e| {
+                    LabeledError::new(format!("Could not read the GFF FASTA section: {e}"))
+                })?;
+                fasta.extend_from_slice(line.as_bytes());
+                fasta.push(b'\n');
+            }
+            break;
+        }
+
+        if let Some(directive) = line.strip_prefix("##") {
+            let mut parts = directive.split_whitespace();
+            match parts.next() {
+                Some("gff-version") => {
+                    version = call.head.with_string_or(parts.next(), "3");
+                }
+                Some("sequence-region") => {
+                    let fields: Vec<&str> = parts.collect();
+                    if let [seqid, start, end] = fields.as_slice() {
+                        let position = |column: &str, value: &str| {
+                            value.parse().map_err(|e| {
+                                LabeledError::new(format!(
+                                    "Line {line_number} has a malformed ##sequence-region {column} {value}: {e}"
+                                ))
+                            })
+                        };
+                        let region = record! {
+                            "start" => Value::int(position("start", start)?, call.head),
+                            "end" => Value::int(position("end", end)?, call.head),
+                        };
+                        sequence_regions.push(*seqid, Value::record(region, call.head));
+                    }
+                }
+                // the resolution directive, nothing to keep.
+                Some("#") => (),
+                _ => other_directives.push(call.head.with_string(directive)),
+            }
+            continue;
+        }
+
+        // comments
+        if line.starts_with('#') {
+            continue;
+        }
+
+        if !sink(parse_record(call, &line, line_number)?) {
+            break;
+        }
+    }
+
+    let header = record! {
+        "version" => version,
+        "sequence_regions" => Value::record(sequence_regions, call.head),
+        "other_directives" => Value::list(other_directives, call.head),
+    };
+
+    Ok((Value::record(header, call.head), fasta))
+}
+
+/// Parse a GFF3 file into a nushell structure.
+///
+/// Returns a record of the header directives, the body of features, and any
+/// sequences from the `##FASTA` section. With `--stream`, only the features
+/// are returned, lazily.
+pub fn from_gff_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    input: PipelineData,
+) -> Result<PipelineData, LabeledError> {
+    let reader = BufReader::new(input_reader(input, call.head)?);
+
+    if call.has_flag("stream")? {
+        let call = call.clone();
+        let stream = stream_values(call.head, signals, move |sink| {
+            parse_gff(&call, reader, sink).map(|_| ())
+        });
+        return Ok(PipelineData::ListStream(stream, None));
+    }
+
+    let (mut header, mut fasta) = (Value::nothing(call.head), Vec::new());
+    let value_records = collect_values(call.head, signals, |sink| {
+        (header, fasta) = parse_gff(call, reader, sink)?;
+        Ok(())
+    })?;
+
+    Ok(PipelineData::Value(
+        Value::record(
+            record! {
+                "header" => header,
+                "body" => Value::list(value_records, call.head),
+                "fasta" => parse_fasta_trailer(call, &fasta)?,
+            },
+            call.head,
+        ),
+        None,
+    ))
+}
+
+/// Query a BGZF compressed GFF for the features overlapping `region`, using
+/// its `.tbi` (or `.csi`) index.
+///
+/// Line numbers in errors count the lines returned by the query.
+pub fn query_gff_inner(
+    call: &EvaluatedCall,
+    signals: &Signals,
+    path: PathBuf,
+    region: &str,
+) -> Result<ListStream, LabeledError> {
+    let region = parse_region(region)?;
+    let index = read_tabix_index(&path)?;
+    let call = call.clone();
+
+    Ok(stream_values(call.head, signals, move |sink| {
+        let mut line_number = 0;
+
+        query_tabix_lines(
+            &path,
+            index.as_ref(),
+            TabixFormat::Gff,
+            &region,
+            &mut |line| {
+                line_number += 1;
+                Ok(sink(parse_record(&call, line, line_number)?))
+            },
+        )
+    }))
+}
+
+/// Encode `%`, control characters and any of the `reserved` characters as
+/// `%XX` escapes, the reverse of [`percent_decode`].
+pub fn percent_encode(s: &str, reserved: &[char]) -> String {
+    let mut encoded = String::with_capacity(s.len());
+
+    for c in s.chars() {
+        if c == '%' || c.is_ascii_control() || reserved.contains(&c) {
+            encoded.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            encoded.push(c);
+        }
+    }
+
+    encoded
+}
+
+/// Characters with a meaning in the attributes column.
+const ATTRIBUTE_RESERVED: &[char] = &[';', '=', '&', ','];
+
+/// Format a single attribute value, which may be a list.
+fn attribute_value_to_gff(value: &Value) -> Result<String, LabeledError> {
+    let text = match value {
+        Value::List { vals, .. } => vals
+            .iter()
+            .map(attribute_value_to_gff)
+            .collect::<Result<Vec<_>, _>>()?
+            .join(","),
+        Value::Nothing { .. } => String::new(),
+        other => percent_encode(&other.coerce_string()?, ATTRIBUTE_RESERVED),
+    };
+
+    Ok(text)
+}
+
+/// Format the attributes record as the ninth column.
+fn attributes_to_gff(value: &Value) -> Result<String, LabeledError> {
+    let attributes = match value {
+        Value::Nothing { .. } => return Ok(".".into()),
+        other => other.as_record()?,
+    };
+
+    if attributes.is_empty() {
+        return Ok(".".into());
+    }
+
+    let fields = attributes
+        .iter()
+        .map(|(key, value)| {
+            Ok(format!(
+                "{}={}",
+                percent_encode(key, ATTRIBUTE_RESERVED),
+                attribute_value_to_gff(value)?
+            ))
+        })
+        .collect::<Result<Vec<_>, LabeledError>>()?;
+
+    Ok(fields.join(";"))
+}
+
+/// Format a row of [`GFF_COLUMNS`] as a GFF3 line.
+fn record_line_from_nuon(row: &Record, i: usize) -> Result<String, LabeledError> {
+    let get = |column: &str| {
+        row.get(column)
+            .ok_or_else(|| LabeledError::new(format!("Row {i} is missing the {column} column")))
+    };
+
+    let optional = |column: &str| -> Result<String, LabeledError> {
+        match get(column)? {
+            Value::Nothing { .. } => Ok(".".into()),
+            other => Ok(percent_encode(&other.coerce_string()?, &[])),
+        }
+    };
+
+    let (start, end) = (get("start")?.as_int()?, get("end")?.as_int()?);
+    if start < 1 || start > end {
+        return Err(LabeledError::new(format!(
+            "Row {i} has start {start} and end {end}, but 1 <= start <= end is required"
+        )));
+    }
+
+    let fields = [
+        // the seqid may not contain anything outside [a-zA-Z0-9.:^*$@!+_?|-].
+        percent_encode(
+            &get("ref_seq_name")?.coerce_string()?,
+            &[' ', '>', ',', ';', '='],
+        ),
+        optional("source")?,
+        optional("ty")?,
+        start.to_string(),
+        end.to_string(),
+        optional("score")?,
+        optional("strand")?,
+        optional("phase")?,
+        attributes_to_gff(get("attributes")?)?,
+    ];
+
+    Ok(fields.join("\t") + "\n")
+}
+
+/// Format the directives from the header record made by [`parse_gff`].
+fn header_text_from_nuon(header: Option<&Record>) -> Result<String, LabeledError> {
+    let version = header
+        .and_then(|h| h.get("version"))
+        .and_then(|v| v.as_str().ok())
+        .unwrap_or("3");
+    let mut text = format!("##gff-version {version}\n");
+
+    let Some(header) = header else {
+        return Ok(text);
+    };
+
+    if let Some(Value::Record { val, .. }) = header.get("sequence_regions") {
+        for (seqid, region) in val.iter() {
+            let region = region.as_record()?;
+            let position = |key: &str| match region.get(key) {
+                Some(v) => v.as_int().map_err(LabeledError::from),
+                None => Err(LabeledError::new(format!(
+                    "Sequence region {seqid} is missing its {key}"
+                ))),
+            };
+            text.push_str(&format!(
+                "##sequence-region {seqid} {} {}\n",
+                position("start")?,
+                position("end")?
+            ));
+        }
+    }
+
+    if let Some(Value::List { vals, .. }) = header.get("other_directives") {
+        for directive in vals {
+            text.push_str(&format!("##{}\n", directive.as_str()?));
+        }
+    }
+
+    Ok(text)
+}
+
+/// Go from the output of `from gff` to GFF3 text.
+///
+/// Takes either the `{header, body, fasta}` record, or a table of features
+/// (e.g. from `from gff --stream`), in which case only `##gff-version 3` is
+/// written before them.
+pub fn nuon_to_gff(call: &EvaluatedCall, input: &Value) -> Result<Value, LabeledError> {
+    let (header, body, fasta) = match input {
+        Value::Record { val, .. } => {
+            let header = match val.get("header") {
+                Some(Value::Record { val, .. }) => Some(&**val),
+                _ => None,
+            };
+            let body = match val.get("body") {
+                Some(body) => body.as_list()?,
+                None => return Err(LabeledError::new("Input has no body: expected the {header, body, fasta} record from `from gff`, or a table of features")),
+            };
+            let fasta = match val.get("fasta") {
+                Some(Value::List { vals, .. }) => vals.as_slice(),
+                _ => &[],
+            };
+            (header, body, fasta)
+        }
+        other => (None, other.as_list()?, &[][..]),
+    };
+
+    let mut out = header_text_from_nuon(header)?;
+
+    for (i, row) in body.iter().enumerate() {
+        out.push_str(&record_line_from_nuon(row.as_record()?, i)?);
+    }
+
+    if !fasta.is_empty() {
+        out.push_str("##FASTA\n");
+        for sequence in fasta {
+            let sequence = sequence.as_record()?;
+            let get = |key: &str| match sequence.get(key) {
+                Some(v) => v.as_str().map_err(LabeledError::from),
+                None => Err(LabeledError::new(format!(
+                    "A sequence in the GFF FASTA section is missing its {key}"
+                ))),
+            };
+            out.push_str(&format!(">{}\n{}\n", get("id")?, get("sequence")?));
+        }
+    }
+
+    Ok(Value::string(out, call.head))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bio_format::tests::test_call;
+    use nu_protocol::Span;
+
+    const GFF: &str = "##gff-version 3
+##sequence-region ctg1 1 1000
+ctg1\tsrc\tgene\t10\t200\t.\t+\t.\tID=gene1;Parent=a,b;Note=a%3Bb
+##FASTA
+>ctg1
+ACGT
+";
+
+    fn from_gff(text: &str) -> Result<Value, LabeledError> {
+        let input = PipelineData::Value(Value::test_string(text), None);
+        let output = from_gff_inner(&test_call(), &Signals::empty(), input)?;
+        Ok(output.into_value(Span::test_data())?)
+    }
+
+    #[test]
+    fn header_features_and_fasta() {
+        let gff = from_gff(GFF).unwrap();
+
+        let header = gff.get_data_by_key("header").unwrap();
+        assert_eq!(
+            header.get_data_by_key("version").unwrap().as_str().unwrap(),
+            "3"
+        );
+        let region = header
+            .get_data_by_key("sequence_regions")
+            .and_then(|r| r.get_data_by_key("ctg1"))
+            .unwrap();
+        assert_eq!(
+            region.get_data_by_key("start").unwrap().as_int().unwrap(),
+            1
+        );
+        assert_eq!(
+            region.get_data_by_key("end").unwrap().as_int().unwrap(),
+            1000
+        );
+
+        let body = gff.get_data_by_key("body").unwrap();
+        let feature = &body.as_list().unwrap()[0];
+        assert_eq!(
+            feature.get_data_by_key("start").unwrap().as_int().unwrap(),
+            10
+        );
+        let attributes = feature.get_data_by_key("attributes").unwrap();
+        assert_eq!(
+            attributes
+                .get_data_by_key("Parent")
+                .unwrap()
+                .as_list()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(
+            attributes
+                .get_data_by_key("Note")
+                .unwrap()
+                .as_str()
+                .unwrap(),
+            "a;b"
+        );
 
-        let mut vec_vals = Vec::new();
-        add_record(call, r, &mut vec_vals);
+        let fasta = gff.get_data_by_key("fasta").unwrap();
+        let sequence = fasta.as_list().unwrap()[0]
+            .get_data_by_key("sequence")
+            .unwrap();
+        assert_eq!(sequence.as_str().unwrap(), "ACGT");
+    }
 
-        let record_inner =
-            Record::from_iter(GFF_COLUMNS.iter().map(|e| e.to_string()).zip(vec_vals));
+    #[test]
+    fn malformed_sequence_region_is_an_error() {
+        let gff = "##gff-version 3\n##sequence-region ctg1 one 1000\n";
+        assert!(from_gff(gff).is_err());
+    }
 
-        value_records.push(Value::record(record_inner, call.head))
-    }*/
+    #[test]
+    fn gff_round_trip() {
+        let gff = nuon_to_gff(&test_call(), &from_gff(GFF).unwrap()).unwrap();
+        assert_eq!(gff.as_str().unwrap(), GFF);
+    }
 
-    Ok(value_records)
+    #[test]
+    fn table_of_features_gets_a_version_line() {
+        let body = from_gff(GFF).unwrap().get_data_by_key("body").unwrap();
+        let gff = nuon_to_gff(&test_call(), &body).unwrap();
+        assert_eq!(
+            gff.as_str().unwrap(),
+            "##gff-version 3\nctg1\tsrc\tgene\t10\t200\t.\t+\t.\tID=gene1;Parent=a,b;Note=a%3Bb\n"
+        );
+    }
 }
@@ -0,0 +1,835 @@
+/// Building the indexes the region queries use, and querying tabix indexes.
+use noodles_bam as bam;
+use noodles_bgzf as bgzf;
+use noodles_core::{Position, Region};
+use noodles_cram as cram;
+use noodles_csi as csi;
+use noodles_fasta as fasta;
+use noodles_sam as sam;
+use noodles_tabix as tabix;
+use nu_plugin::EvaluatedCall;
+use nu_protocol::LabeledError;
+use nu_protocol::{record, Value};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use csi::binning_index::index::header::Builder as TabixHeaderBuilder;
+use csi::binning_index::index::reference_sequence::{self, bin::Chunk};
+use csi::binning_index::{Index, Indexer, ReferenceSequence as _};
+use csi::BinningIndex;
+use sam::alignment::Record as AlignmentRecord;
+
+use super::cram::{decode_records, reference_hint, reference_repository};
+use super::{find_index, sidecar_path, SpanExt};
+
+/// The kinds of file we can index, taken from the file name.
+enum IndexKind {
+    /// `.fai`, plus a `.gzi` if BGZF compressed.
+    Fasta { bgzf: bool },
+    /// `.bai`, or `.csi` if asked for.
+    Bam,
+    /// `.crai`
+    Cram,
+    /// `.tbi` for BGZF compressed VCF, BED and GFF.
+    Tabix(TabixFormat),
+}
+
+/// The formats tabix can index, and how to find a record's interval.
+#[derive(Clone, Copy)]
+pub enum TabixFormat {
+    Vcf,
+    Bed,
+    Gff,
+}
+
+impl IndexKind {
+    fn from_path(path: &Path) -> Result<Self, LabeledError> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        let (stem, compressed) = match name
+            .strip_suffix(".gz")
+            .or_else(|| name.strip_suffix(".bgz"))
+        {
+            Some(stem) => (stem, true),
+            None => (name.as_str(), false),
+        };
+        let extension = stem.rsplit('.').next().unwrap_or_default();
+
+        let kind = match (extension, compressed) {
+            ("fa" | "fasta" | "fna" | "fas", bgzf) => IndexKind::Fasta { bgzf },
+            ("bam", false) => IndexKind::Bam,
+            ("cram", false) => IndexKind::Cram,
+            ("vcf", true) => IndexKind::Tabix(TabixFormat::Vcf),
+            ("bed", true) => IndexKind::Tabix(TabixFormat::Bed),
+            ("gff" | "gff3", true) => IndexKind::Tabix(TabixFormat::Gff),
+            _ => {
+                return Err(LabeledError::new(format!(
+                    "Don't know how to index {}: expected a fasta (optionally BGZF compressed), BAM, CRAM, or BGZF compressed VCF, BED or GFF",
+                    path.display()
+                )))
+            }
+        };
+
+        Ok(kind)
+    }
+}
+
+/// Turn an I/O error raised while indexing into a [`LabeledError`].
+fn index_error(path: &Path) -> impl Fn(io::Error) -> LabeledError + '_ {
+    move |e| {
+        LabeledError::new(format!(
+            "Could not index {}. cause of failure: {}",
+            path.display(),
+            e
+        ))
+    }
+}
+
+/// Walk the blocks of a BGZF file, returning the compressed and uncompressed
+/// offset of the start of each block after the first, which is a GZI index.
+fn build_gzi(path: &Path) -> io::Result<Vec<(u64, u64)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    let (mut compressed, mut uncompressed) = (0u64, 0u64);
+
+    // the gzip header, with the BC extra subfield holding the block size.
+    let mut header = [0u8; 18];
+
+    loop {
+        match reader.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            result => result?,
+        }
+
+        if header[..4] != [0x1f, 0x8b, 0x08, 0x04] || &header[12..14] != b"BC" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not BGZF compressed (try bgzip rather than gzip)",
+            ));
+        }
+
+        let block_size = u16::from_le_bytes([header[16], header[17]]) as usize + 1;
+        let mut rest = vec![0; block_size.saturating_sub(header.len())];
+        reader.read_exact(&mut rest)?;
+
+        // the block ends with the size of its uncompressed data.
+        let trailer: [u8; 4] = rest[rest.len().saturating_sub(4)..]
+            .try_into()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "truncated BGZF block"))?;
+        let data_size = u32::from_le_bytes(trailer) as u64;
+
+        if compressed > 0 && data_size > 0 {
+            entries.push((compressed, uncompressed));
+        }
+
+        compressed += block_size as u64;
+        uncompressed += data_size;
+    }
+
+    Ok(entries)
+}
+
+/// Write a GZI: the number of entries, then each pair of offsets, all as
+/// little endian u64s.
+fn write_gzi(path: &Path, entries: &[(u64, u64)]) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(File::create(path)?);
+
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+    for (compressed, uncompressed) in entries {
+        writer.write_all(&compressed.to_le_bytes())?;
+        writer.write_all(&uncompressed.to_le_bytes())?;
+    }
+
+    writer.flush()
+}
+
+/// Index a fasta, writing the `.fai` (and the `.gzi` if BGZF compressed).
+fn index_fasta(call: &EvaluatedCall, path: &Path, bgzf: bool) -> Result<Vec<Value>, LabeledError> {
+    let to_error = index_error(path);
+
+    let file = File::open(path).map_err(&to_error)?;
+    // offsets in the .fai are into the uncompressed fasta.
+    let reader: Box<dyn BufRead> = match bgzf {
+        true => Box::new(bgzf::io::Reader::new(file)),
+        false => Box::new(BufReader::new(file)),
+    };
+
+    let mut indexer = fasta::io::Indexer::new(reader);
+    let mut records = Vec::new();
+    while let Some(record) = indexer.index_record().map_err(|e| {
+        LabeledError::new(format!(
+            "Could not index {}. cause of failure: {}",
+            path.display(),
+            e
+        ))
+    })? {
+        records.push(record);
+    }
+
+    let rows = records
+        .iter()
+        .map(|r| {
+            Value::record(
+                record! {
+                    "name" => call.head.with_string(String::from_utf8_lossy(r.name().as_ref())),
+                    "length" => Value::int(r.length() as i64, call.head),
+                    "offset" => Value::int(r.offset() as i64, call.head),
+                    "line_bases" => Value::int(r.line_bases() as i64, call.head),
+                    "line_width" => Value::int(r.line_width() as i64, call.head),
+                },
+                call.head,
+            )
+        })
+        .collect();
+
+    let index = fasta::fai::Index::from(records);
+    let mut writer =
+        fasta::fai::io::Writer::new(File::create(sidecar_path(path, "fai")).map_err(&to_error)?);
+    writer.write_index(&index).map_err(&to_error)?;
+
+    if bgzf {
+        let entries = build_gzi(path).map_err(&to_error)?;
+        write_gzi(&sidecar_path(path, "gzi"), &entries).map_err(&to_error)?;
+    }
+
+    Ok(rows)
+}
+
+/// One row per reference sequence of a binning index (BAI, CSI or tabix).
+fn binning_index_rows<I>(call: &EvaluatedCall, reference_sequences: I) -> Vec<Value>
+where
+    I: Iterator<Item = (String, usize, Option<(u64, u64)>)>,
+{
+    reference_sequences
+        .map(|(name, bins, counts)| {
+            let (mapped, unmapped) = match counts {
+                Some((mapped, unmapped)) => (
+                    Value::int(mapped as i64, call.head),
+                    Value::int(unmapped as i64, call.head),
+                ),
+                None => (Value::nothing(call.head), Value::nothing(call.head)),
+            };
+
+            Value::record(
+                record! {
+                    "reference_sequence" => call.head.with_string(name),
+                    "bins" => Value::int(bins as i64, call.head),
+                    "mapped" => mapped,
+                    "unmapped" => unmapped,
+                },
+                call.head,
+            )
+        })
+        .collect()
+}
+
+/// Read a coordinate sorted BAM through to the end, building a BAI or a CSI
+/// depending on the index type asked for.
+fn build_bam_index<R, I>(
+    reader: &mut bam::io::Reader<bgzf::io::Reader<R>>,
+    reference_sequence_count: usize,
+) -> io::Result<Index<I>>
+where
+    R: Read,
+    I: reference_sequence::Index + Default,
+{
+    let mut indexer = Indexer::<I>::default();
+    let mut record = bam::Record::default();
+    let mut start = reader.get_ref().virtual_position();
+
+    while reader.read_record(&mut record)? != 0 {
+        let end = reader.get_ref().virtual_position();
+
+        let alignment_context = match (
+            record.reference_sequence_id().transpose()?,
+            record.alignment_start().transpose()?,
+            // the end comes from the start and the CIGAR's reference span.
+            AlignmentRecord::alignment_end(&record).transpose()?,
+        ) {
+            (Some(id), Some(alignment_start), Some(alignment_end)) => Some((
+                id,
+                alignment_start,
+                alignment_end,
+                !record.flags().is_unmapped(),
+            )),
+            _ => None,
+        };
+
+        indexer.add_record(alignment_context, Chunk::new(start, end))?;
+
+        start = end;
+    }
+
+    Ok(indexer.build(reference_sequence_count))
+}
+
+/// One row per reference sequence of a BAI or CSI, named from the header.
+fn bam_index_rows<I>(call: &EvaluatedCall, header: &sam::Header, index: &Index<I>) -> Vec<Value>
+where
+    I: reference_sequence::Index,
+{
+    binning_index_rows(
+        call,
+        header
+            .reference_sequences()
+            .keys()
+            .zip(index.reference_sequences())
+            .map(|(name, r)| {
+                (
+                    name.to_string(),
+                    r.bins().len(),
+                    r.metadata()
+                        .map(|m| (m.mapped_record_count(), m.unmapped_record_count())),
+                )
+            }),
+    )
+}
+
+/// Index a coordinate sorted BAM, writing a `.bai`, or a `.csi` if `csi`.
+fn index_bam(call: &EvaluatedCall, path: &Path, csi: bool) -> Result<Vec<Value>, LabeledError> {
+    let to_error = index_error(path);
+
+    let mut reader = File::open(path)
+        .map(bam::io::Reader::new)
+        .map_err(&to_error)?;
+    let header = reader.read_header().map_err(&to_error)?;
+    let reference_sequence_count = header.reference_sequences().len();
+
+    let rows = match csi {
+        true => {
+            let index: csi::Index =
+                build_bam_index(&mut reader, reference_sequence_count).map_err(&to_error)?;
+            csi::fs::write(sidecar_path(path, "csi"), &index).map_err(&to_error)?;
+            bam_index_rows(call, &header, &index)
+        }
+        false => {
+            let index: bam::bai::Index =
+                build_bam_index(&mut reader, reference_sequence_count).map_err(&to_error)?;
+            bam::bai::fs::write(sidecar_path(path, "bai"), &index).map_err(&to_error)?;
+            bam_index_rows(call, &header, &index)
+        }
+    };
+
+    Ok(rows)
+}
+
+/// Read every container of a CRAM, adding a `.crai` record for each
+/// reference sequence of each slice, spanning the slice's records on it.
+fn build_crai<R: Read + Seek>(
+    reader: &mut cram::io::Reader<R>,
+    header: &sam::Header,
+    repository: &fasta::Repository,
+) -> io::Result<cram::crai::Index> {
+    let mut index = Vec::new();
+
+    let mut container = cram::io::reader::Container::default();
+    let mut container_position = reader.position()?;
+
+    loop {
+        let container_len = match reader.read_container(&mut container)? {
+            0 => break,
+            n => n,
+        };
+
+        let compression_header = container.compression_header()?;
+        let landmarks = container.header().landmarks();
+
+        for (i, result) in container.slices().enumerate() {
+            let slice = result?;
+            let landmark = landmarks[i];
+            let slice_length = landmarks.get(i + 1).unwrap_or(&container_len) - landmark;
+
+            let (core_data, external_data) = slice.decode_blocks()?;
+            let records = slice.records(
+                repository.clone(),
+                header,
+                &compression_header,
+                &core_data,
+                &external_data,
+            )?;
+
+            // the first start and last end on each reference sequence, unmapped first.
+            let mut spans: BTreeMap<Option<usize>, Option<(Position, Position)>> = BTreeMap::new();
+            for record in &records {
+                let id = record.reference_sequence_id(header).transpose()?;
+                let span = spans.entry(id).or_default();
+
+                let start = record.alignment_start().transpose()?;
+                let end = AlignmentRecord::alignment_end(record).transpose()?;
+                if let (Some(start), Some(end)) = (start, end) {
+                    *span = Some(match *span {
+                        Some((s, e)) => (s.min(start), e.max(end)),
+                        None => (start, end),
+                    });
+                }
+            }
+
+            for (id, span) in spans {
+                let (alignment_start, alignment_span) = match span {
+                    Some((start, end)) if id.is_some() => {
+                        (Some(start), usize::from(end) - usize::from(start) + 1)
+                    }
+                    _ => (None, 0),
+                };
+
+                index.push(cram::crai::Record::new(
+                    id,
+                    alignment_start,
+                    alignment_span,
+                    container_position,
+                    landmark as u64,
+                    slice_length as u64,
+                ));
+            }
+        }
+
+        container_position = reader.position()?;
+    }
+
+    Ok(index)
+}
+
+/// Index a CRAM, writing a `.crai`.
+///
+/// The records are decoded to find their spans, so a CRAM compressed against
+/// a reference needs it.
+fn index_cram(
+    call: &EvaluatedCall,
+    path: &Path,
+    reference: Option<PathBuf>,
+) -> Result<Vec<Value>, LabeledError> {
+    let to_error = index_error(path);
+
+    let repository = match &reference {
+        Some(reference) => reference_repository(reference)?,
+        None => fasta::Repository::default(),
+    };
+
+    let mut reader = File::open(path)
+        .map(cram::io::Reader::new)
+        .map_err(&to_error)?;
+    let header = reader.read_header().map_err(&to_error)?;
+
+    let mut index = Vec::new();
+    decode_records(
+        || {
+            index = build_crai(&mut reader, &header, &repository).map_err(&to_error)?;
+            Ok(())
+        },
+        reference_hint(&reference),
+    )?;
+
+    let rows = index
+        .iter()
+        .map(|r| {
+            Value::record(
+                record! {
+                    "reference_sequence_id" => match r.reference_sequence_id() {
+                        Some(id) => Value::int(id as i64, call.head),
+                        None => Value::nothing(call.head),
+                    },
+                    "alignment_start" => match r.alignment_start() {
+                        Some(start) => Value::int(usize::from(start) as i64, call.head),
+                        None => Value::nothing(call.head),
+                    },
+                    "alignment_span" => Value::int(r.alignment_span() as i64, call.head),
+                    "offset" => Value::int(r.offset() as i64, call.head),
+                    "landmark" => Value::int(r.landmark() as i64, call.head),
+                    "slice_length" => Value::int(r.slice_length() as i64, call.head),
+                },
+                call.head,
+            )
+        })
+        .collect();
+
+    // crai::fs::write opens rather than creates the file, so write it here.
+    let mut writer = File::create(sidecar_path(path, "crai"))
+        .map(cram::crai::io::Writer::new)
+        .map_err(&to_error)?;
+    writer.write_index(&index).map_err(&to_error)?;
+    writer.finish().map_err(&to_error)?;
+
+    Ok(rows)
+}
+
+/// The reference sequence name and 1-based, inclusive interval of a line.
+fn tabix_interval(format: TabixFormat, line: &str) -> io::Result<(&str, Position, Position)> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{msg}: {line}"));
+    let fields: Vec<&str> = line.split('\t').collect();
+
+    let int = |i: usize| -> io::Result<usize> {
+        fields
+            .get(i)
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| invalid("malformed position"))
+    };
+
+    let (start, end) = match format {
+        // the end is INFO/END if there is one (e.g. for a structural variant),
+        // or the end of REF.
+        TabixFormat::Vcf => {
+            let reference_bases = fields.get(3).ok_or_else(|| invalid("missing REF"))?;
+            let start = int(1)?;
+            let info_end = fields
+                .get(7)
+                .and_then(|info| info.split(';').find_map(|f| f.strip_prefix("END=")));
+            match info_end {
+                Some(end) => (start, end.parse().map_err(|_| invalid("malformed END"))?),
+                None => (start, start + reference_bases.len().max(1) - 1),
+            }
+        }
+        // 0-based, half open.
+        TabixFormat::Bed => (int(1)? + 1, int(2)?),
+        TabixFormat::Gff => (int(3)?, int(4)?),
+    };
+
+    let position = |n: usize| Position::try_from(n).map_err(|_| invalid("position 0"));
+
+    Ok((fields[0], position(start)?, position(end.max(start))?))
+}
+
+/// Index a BGZF compressed VCF, BED or GFF, writing a `.tbi`.
+fn index_tabix(
+    call: &EvaluatedCall,
+    path: &Path,
+    format: TabixFormat,
+) -> Result<Vec<Value>, LabeledError> {
+    let to_error = index_error(path);
+
+    let mut reader = File::open(path)
+        .map(bgzf::io::Reader::new)
+        .map_err(&to_error)?;
+
+    let header = match format {
+        TabixFormat::Vcf => TabixHeaderBuilder::vcf(),
+        TabixFormat::Bed => TabixHeaderBuilder::bed(),
+        TabixFormat::Gff => TabixHeaderBuilder::gff(),
+    }
+    .build();
+
+    let mut indexer = tabix::index::Indexer::default();
+    indexer.set_header(header);
+
+    let mut line = String::new();
+    let mut start = reader.virtual_position();
+
+    while reader.read_line(&mut line).map_err(&to_error)? != 0 {
+        let end = reader.virtual_position();
+        let record = line.trim_end_matches(['\n', '\r']);
+
+        // the sequences at the end of a GFF3 are not features.
+        if matches!(format, TabixFormat::Gff) && (record == "##FASTA" || record.starts_with('>')) {
+            break;
+        }
+
+        let is_header = record.is_empty()
+            || record.starts_with('#')
+            || record.starts_with("track")
+            || record.starts_with("browser");
+
+        if !is_header {
+            let (name, alignment_start, alignment_end) =
+                tabix_interval(format, record).map_err(&to_error)?;
+            indexer
+                .add_record(name, alignment_start, alignment_end, Chunk::new(start, end))
+                .map_err(&to_error)?;
+        }
+
+        line.clear();
+        start = end;
+    }
+
+    let index = indexer.build();
+
+    let names: Vec<String> = index
+        .header()
+        .map(|h| {
+            h.reference_sequence_names()
+                .iter()
+                .map(|n| String::from_utf8_lossy(n.as_ref()).to_string())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let rows = binning_index_rows(
+        call,
+        names
+            .into_iter()
+            .zip(index.reference_sequences())
+            .map(|(name, r)| {
+                (
+                    name,
+                    r.bins().len(),
+                    r.metadata()
+                        .map(|m| (m.mapped_record_count(), m.unmapped_record_count())),
+                )
+            }),
+    );
+
+    tabix::fs::write(sidecar_path(path, "tbi"), &index).map_err(&to_error)?;
+
+    Ok(rows)
+}
+
+/// Read the `.tbi` (or `.csi`) next to a BGZF compressed file.
+pub fn read_tabix_index(path: &Path) -> Result<Box<dyn BinningIndex + Send>, LabeledError> {
+    let index_path = find_index(path, &["tbi", "csi"])?;
+
+    let index_error = |e: io::Error| {
+        LabeledError::new(format!(
+            "Could not read the index {}. cause of failure: {}",
+            index_path.display(),
+            e
+        ))
+    };
+
+    Ok(match index_path.extension().and_then(|e| e.to_str()) {
+        Some("csi") => Box::new(csi::fs::read(&index_path).map_err(index_error)?),
+        _ => Box::new(tabix::fs::read(&index_path).map_err(index_error)?),
+    })
+}
+
+/// Send the lines of a BGZF compressed VCF, BED or GFF that overlap `region`
+/// to `on_line`, reading only the blocks the tabix index points to. Stops
+/// once `on_line` returns `false`.
+pub fn query_tabix_lines(
+    path: &Path,
+    index: &dyn BinningIndex,
+    format: TabixFormat,
+    region: &Region,
+    on_line: &mut dyn FnMut(&str) -> Result<bool, LabeledError>,
+) -> Result<(), LabeledError> {
+    let to_error = index_error(path);
+
+    let reference_sequence_id = index
+        .header()
+        .and_then(|h| h.reference_sequence_names().get_index_of(region.name()))
+        .ok_or_else(|| {
+            LabeledError::new(format!(
+                "{} is not in the index of {}",
+                String::from_utf8_lossy(AsRef::<[u8]>::as_ref(region.name())),
+                path.display()
+            ))
+        })?;
+
+    let interval = region.interval();
+    let chunks = index
+        .query(reference_sequence_id, interval)
+        .map_err(&to_error)?;
+
+    let mut reader = File::open(path)
+        .map(bgzf::io::Reader::new)
+        .map_err(&to_error)?;
+    let mut line = String::new();
+
+    for chunk in chunks {
+        reader.seek(chunk.start()).map_err(&to_error)?;
+
+        while reader.virtual_position() < chunk.end() {
+            line.clear();
+            if reader.read_line(&mut line).map_err(&to_error)? == 0 {
+                break;
+            }
+
+            let record = line.trim_end_matches(['\n', '\r']);
+            if record.is_empty() || record.starts_with('#') {
+                continue;
+            }
+
+            // the bins are coarser than the region, so check each record.
+            let (name, start, end) = tabix_interval(format, record).map_err(&to_error)?;
+            let overlaps = name.as_bytes() == AsRef::<[u8]>::as_ref(region.name())
+                && interval.end().is_none_or(|region_end| start <= region_end)
+                && interval
+                    .start()
+                    .is_none_or(|region_start| end >= region_start);
+
+            if overlaps && !on_line(record)? {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Build the index of a file next to it, picking the kind of index from the
+/// file name. Returns the records of the index as a table.
+///
+/// `reference` is the fasta a CRAM was compressed against.
+pub fn index_inner(
+    call: &EvaluatedCall,
+    path: PathBuf,
+    reference: Option<PathBuf>,
+) -> Result<Value, LabeledError> {
+    let csi = call.has_flag("csi")?;
+
+    let rows = match IndexKind::from_path(&path)? {
+        IndexKind::Fasta { bgzf } => index_fasta(call, &path, bgzf)?,
+        IndexKind::Bam => index_bam(call, &path, csi)?,
+        IndexKind::Cram => index_cram(call, &path, reference)?,
+        IndexKind::Tabix(format) => index_tabix(call, &path, format)?,
+    };
+
+    Ok(Value::list(rows, call.head))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bio_format::bam::query_bam_inner;
+    use crate::bio_format::bcf::query_vcf_inner;
+    use crate::bio_format::tests::{call_with_switches, collect, fixture, fixture_copy, test_call};
+    use nu_protocol::Signals;
+    use std::fs;
+
+    /// Index the sorted BAM as a `.bai` or `.csi`, then query it.
+    fn bam_index_round_trip(csi: bool) {
+        let extension = if csi { "csi" } else { "bai" };
+        let path = fixture_copy(
+            "map_sorted.bam",
+            &format!("bam_index_round_trip_{extension}"),
+        );
+        let call = match csi {
+            true => call_with_switches(&["csi"]),
+            false => test_call(),
+        };
+
+        index_inner(&call, path.clone(), None).unwrap();
+        assert!(sidecar_path(&path, extension).exists());
+
+        // count the overlapping alignments by reading the whole file.
+        let (start, end) = (1000, 2000);
+        let mut reader = File::open(&path).map(bam::io::Reader::new).unwrap();
+        let header = reader.read_header().unwrap();
+        let expected = reader
+            .records()
+            .map(|r| r.unwrap())
+            .filter(|r| {
+                match (
+                    r.alignment_start().transpose().unwrap(),
+                    AlignmentRecord::alignment_end(r).transpose().unwrap(),
+                ) {
+                    (Some(s), Some(e)) => usize::from(s) <= end && usize::from(e) >= start,
+                    _ => false,
+                }
+            })
+            .count();
+        let name = header
+            .reference_sequences()
+            .keys()
+            .next()
+            .unwrap()
+            .to_string();
+
+        let region = format!("{name}:{start}-{end}");
+        let rows =
+            collect(query_bam_inner(&call, &Signals::empty(), path.clone(), &region).unwrap());
+
+        assert!(expected > 0);
+        assert_eq!(rows.len(), expected);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn bai_round_trip() {
+        bam_index_round_trip(false);
+    }
+
+    #[test]
+    fn csi_round_trip() {
+        bam_index_round_trip(true);
+    }
+
+    #[test]
+    fn tabix_index_round_trip() {
+        let path = fixture_copy("map.vcf.gz", "tabix_index_round_trip");
+        let call = test_call();
+
+        index_inner(&call, path.clone(), None).unwrap();
+        assert!(sidecar_path(&path, "tbi").exists());
+
+        let rows = collect(
+            query_vcf_inner(
+                &call,
+                &Signals::empty(),
+                path.clone(),
+                "drAilAlti1:1000-4000",
+            )
+            .unwrap(),
+        );
+        let positions: Vec<i64> = rows
+            .iter()
+            .map(|row| row.get_data_by_key("pos").unwrap().as_int().unwrap())
+            .collect();
+
+        assert_eq!(positions, vec![1044, 3821, 3825]);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn cram_index_with_reference() {
+        let path = fixture_copy("map.cram", "cram_index_with_reference");
+        let call = test_call();
+
+        let rows = index_inner(&call, path.clone(), Some(fixture("drAilAlti1.fa"))).unwrap();
+        assert!(sidecar_path(&path, "crai").exists());
+
+        let rows = rows.as_list().unwrap();
+        assert!(!rows.is_empty());
+        let id = rows[0].get_data_by_key("reference_sequence_id").unwrap();
+        assert_eq!(id.as_int().unwrap(), 0);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn vcf_interval_ends_at_info_end() {
+        let line = "chr1\t100\t.\tN\t<DEL>\t.\tPASS\tSVTYPE=DEL;END=500";
+        let (name, start, end) = tabix_interval(TabixFormat::Vcf, line).unwrap();
+
+        assert_eq!(name, "chr1");
+        assert_eq!(usize::from(start), 100);
+        assert_eq!(usize::from(end), 500);
+    }
+
+    #[test]
+    fn gff_index_stops_at_the_fasta() {
+        let dir = fixture_copy("map.gfa", "gff_index_stops_at_the_fasta");
+        let path = dir.with_file_name("features.gff.gz");
+        let gff = "##gff-version 3
+ctg1\tsrc\tgene\t10\t200\t.\t+\t.\tID=gene1
+##FASTA
+>ctg1
+ACGT
+";
+        fs::write(
+            &path,
+            crate::bio_format::bgzf_compress(gff.as_bytes()).unwrap(),
+        )
+        .unwrap();
+
+        let rows = index_inner(&test_call(), path.clone(), None).unwrap();
+        assert!(sidecar_path(&path, "tbi").exists());
+        assert_eq!(rows.as_list().unwrap().len(), 1);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn cram_index_without_its_reference_is_an_error() {
+        let path = fixture_copy("map.cram", "cram_index_without_its_reference");
+
+        let err = index_inner(&test_call(), path.clone(), None).unwrap_err();
+        assert!(err.msg.contains("--reference"), "{}", err.msg);
+
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
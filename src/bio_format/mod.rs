@@ -1,4 +1,11 @@
+use noodles_bgzf as bgzf;
+use noodles_core::Region;
+use nu_protocol::{record, LabeledError, ListStream, PipelineData, ShellError, Signals};
 pub use nu_protocol::{Span, Value};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
 /// SAM + BAM parsing facility.
 pub mod bam;
 /// BCF + VCF parsing facility.
@@ -13,6 +20,8 @@ pub mod fasta;
 pub mod gfa;
 /// GFF(3) parsing facility
 pub mod gff;
+/// Index building facility.
+pub mod index;
 
 /// Compression enum
 pub enum Compression {
@@ -20,6 +29,199 @@ pub enum Compression {
     Gzipped,
 }
 
+/// How many decoded values may sit waiting for the consumer of a stream.
+const STREAM_BUFFER: usize = 1024;
+
+/// The readers handed to the parsers.
+pub type InputReader = Box<dyn Read + Send>;
+
+/// Turn the input into a reader. Byte streams (e.g. from `open --raw`) are
+/// read incrementally, and binary or string values are read without copying.
+pub fn input_reader(input: PipelineData, span: Span) -> Result<InputReader, LabeledError> {
+    if let PipelineData::ByteStream(stream, ..) = input {
+        return Ok(match stream.reader() {
+            Some(reader) => Box::new(reader),
+            // an empty stream
+            None => Box::new(io::empty()),
+        });
+    }
+
+    match input.into_value(span)? {
+        Value::Binary { val, .. } => Ok(Box::new(Cursor::new(val))),
+        Value::String { val, .. } => Ok(Box::new(Cursor::new(val.into_bytes()))),
+        other => Err(LabeledError::new(format!(
+            "Input must be binary or string data, got {}",
+            other.get_type()
+        ))),
+    }
+}
+
+/// Where parsers send each decoded value. Returns `false` once nothing is
+/// listening any more, at which point the parser should stop.
+pub type Sink<'a> = &'a mut dyn FnMut(Value) -> bool;
+
+/// Run `producer` on its own thread, and stream the values it sends lazily.
+///
+/// The channel is bounded, so the producer only reads ahead a little, and it
+/// stops as soon as the stream is dropped (e.g. after `first 10`), or on
+/// Ctrl-C.
+pub fn stream_values<F>(span: Span, signals: &Signals, producer: F) -> ListStream
+where
+    F: FnOnce(Sink) -> Result<(), LabeledError> + Send + 'static,
+{
+    let (tx, rx) = mpsc::sync_channel(STREAM_BUFFER);
+    let producer_signals = signals.clone();
+
+    thread::spawn(move || {
+        let mut sink = |value: Value| !producer_signals.interrupted() && tx.send(value).is_ok();
+        if let Err(e) = producer(&mut sink) {
+            let _ = tx.send(Value::error(ShellError::from(e), span));
+        }
+    });
+
+    ListStream::new(rx.into_iter(), span, signals.clone())
+}
+
+/// Run `producer` to completion on this thread, collecting every value.
+/// Stops with an error on Ctrl-C.
+pub fn collect_values<F>(
+    span: Span,
+    signals: &Signals,
+    producer: F,
+) -> Result<Vec<Value>, LabeledError>
+where
+    F: FnOnce(Sink) -> Result<(), LabeledError>,
+{
+    let mut values = Vec::new();
+    producer(&mut |value| {
+        values.push(value);
+        !signals.interrupted()
+    })?;
+    signals.check(span)?;
+
+    Ok(values)
+}
+
+/// The output of the formats with a header: either a lazy stream of the body
+/// records alone, or a record of the header and the collected body.
+pub fn header_body_output<F>(
+    span: Span,
+    signals: &Signals,
+    stream: bool,
+    header: Value,
+    producer: F,
+) -> Result<PipelineData, LabeledError>
+where
+    F: FnOnce(Sink) -> Result<(), LabeledError> + Send + 'static,
+{
+    if stream {
+        return Ok(PipelineData::ListStream(
+            stream_values(span, signals, producer),
+            None,
+        ));
+    }
+
+    let body = collect_values(span, signals, producer)?;
+
+    Ok(PipelineData::Value(
+        Value::record(
+            record! {
+                "header" => header,
+                "body" => Value::list(body, span),
+            },
+            span,
+        ),
+        None,
+    ))
+}
+
+/// Compress `bytes` as BGZF, the blocked gzip that `bgzip` writes, so the
+/// output can be indexed (e.g. with a `.gzi`) and read by anything gzip aware.
+pub fn bgzf_compress(bytes: &[u8]) -> Result<Vec<u8>, LabeledError> {
+    let mut writer = bgzf::io::Writer::new(Vec::new());
+    writer
+        .write_all(bytes)
+        .map_err(|e| LabeledError::new(format!("Could not BGZF compress the output: {}", e)))?;
+
+    writer
+        .finish()
+        .map_err(|e| LabeledError::new(format!("Could not finish the BGZF stream: {}", e)))
+}
+
+/// The path of a sidecar file (index, etc.) that sits next to `path`, e.g.
+/// `ref.fa` -> `ref.fa.fai`.
+pub fn sidecar_path(path: &Path, extension: &str) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(".");
+    sidecar.push(extension);
+    PathBuf::from(sidecar)
+}
+
+/// The first of the sidecar indexes of `path` that exists, e.g. the `.bai` or
+/// `.csi` of a BAM.
+pub fn find_index(path: &Path, extensions: &[&str]) -> Result<PathBuf, LabeledError> {
+    extensions
+        .iter()
+        .map(|extension| sidecar_path(path, extension))
+        .find(|index| index.exists())
+        .ok_or_else(|| {
+            LabeledError::new(format!(
+                "No index found for {}: expected a .{} next to it",
+                path.display(),
+                extensions.join(" or .")
+            ))
+        })
+}
+
+/// Parse a region such as `chr1`, `chr1:100` or `chr1:100-200` (1-based and
+/// inclusive, as in samtools).
+pub fn parse_region(region: &str) -> Result<Region, LabeledError> {
+    region.parse().map_err(|e| {
+        LabeledError::new(format!(
+            "Could not parse the region {region}, expected chr:start-end: {e}"
+        ))
+    })
+}
+
+/// Read regions from the input: a string, a list of strings, or a table with
+/// either a `region` column, or BED columns (`chrom`, `chromStart`, `chromEnd`,
+/// which are 0-based and half open).
+pub fn regions_from_value(input: &Value) -> Result<Vec<String>, LabeledError> {
+    let region = |value: &Value| -> Result<String, LabeledError> {
+        match value {
+            Value::String { val, .. } => Ok(val.clone()),
+            Value::Record { val, .. } => {
+                if let Some(region) = val.get("region") {
+                    return Ok(region.as_str()?.to_string());
+                }
+
+                match (val.get("chrom"), val.get("chromStart"), val.get("chromEnd")) {
+                    (Some(chrom), Some(start), Some(end)) => Ok(format!(
+                        "{}:{}-{}",
+                        chrom.as_str()?,
+                        start.as_int()? + 1,
+                        end.as_int()?
+                    )),
+                    _ => Err(LabeledError::new(
+                        "Region rows need a region column, or chrom, chromStart and chromEnd columns",
+                    )
+                    .with_label("in this row", value.span())),
+                }
+            }
+            other => Err(LabeledError::new(format!(
+                "Regions must be strings or records, got {}",
+                other.get_type()
+            ))),
+        }
+    };
+
+    match input {
+        Value::Nothing { .. } => Ok(Vec::new()),
+        Value::List { vals, .. } => vals.iter().map(region).collect(),
+        other => Ok(vec![region(other)?]),
+    }
+}
+
 pub trait SpanExt {
     fn with_string<S: ToString>(&self, s: S) -> Value;
     fn with_string_or<S: ToString>(&self, s: Option<S>, default: &str) -> Value;
@@ -39,3 +241,71 @@ impl SpanExt for Span {
         self.with_string(std::str::from_utf8(s).unwrap())
     }
 }
+
+/// Helpers for the tests of the parsers and writers.
+#[cfg(test)]
+pub mod tests {
+    use nu_plugin::EvaluatedCall;
+    use nu_protocol::{LabeledError, ListStream, Span, Spanned, Value};
+    use std::path::{Path, PathBuf};
+
+    /// A call with no arguments.
+    pub fn test_call() -> EvaluatedCall {
+        EvaluatedCall {
+            head: Span::test_data(),
+            positional: Vec::new(),
+            named: Vec::new(),
+        }
+    }
+
+    /// A call with the given switches set, e.g. `--long`.
+    pub fn call_with_switches(switches: &[&str]) -> EvaluatedCall {
+        let mut call = test_call();
+        for switch in switches {
+            let name = Spanned {
+                item: switch.to_string(),
+                span: call.head,
+            };
+            call.named.push((name, None));
+        }
+        call
+    }
+
+    /// The path of a file in the `tests` directory.
+    pub fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests")
+            .join(name)
+    }
+
+    /// Copy a fixture into a fresh directory named after the test, so an
+    /// index can be built next to it.
+    pub fn fixture_copy(name: &str, test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nu_plugin_bio_{test}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join(name);
+        std::fs::copy(fixture(name), &path).unwrap();
+        path
+    }
+
+    /// Collect a stream, returning the first error value in it.
+    pub fn try_collect(stream: ListStream) -> Result<Vec<Value>, LabeledError> {
+        stream
+            .into_iter()
+            .map(|v| match v {
+                Value::Error { error, .. } => Err(LabeledError::from(*error)),
+                v => Ok(v),
+            })
+            .collect()
+    }
+
+    /// Collect a stream, failing on any error values in it.
+    pub fn collect(stream: ListStream) -> Vec<Value> {
+        stream
+            .into_iter()
+            .inspect(|v| assert!(!v.is_error(), "{v:?}"))
+            .collect()
+    }
+}
@@ -1,17 +1,25 @@
-use crate::bio_format::Compression;
 use crate::bio::Bio;
-use nu_plugin::{EngineInterface, EvaluatedCall, Plugin, SimplePluginCommand};
+use crate::bio_format::{regions_from_value, Compression};
+use nu_plugin::{EngineInterface, EvaluatedCall, Plugin, PluginCommand};
 use nu_protocol::LabeledError;
-use nu_protocol::{Category, Signature, Type, Value};
+use nu_protocol::{Category, PipelineData, Signature, SyntaxShape, Type};
+use std::path::PathBuf;
 
 pub struct BioPlugin;
 
+/// Resolve a path argument against nushell's current directory, as the plugin
+/// runs in its own process.
+fn resolve_path(engine: &EngineInterface, path: &str) -> Result<PathBuf, LabeledError> {
+    let cwd = engine.get_current_dir()?;
+    Ok(PathBuf::from(cwd).join(path))
+}
+
 impl Plugin for BioPlugin {
     fn version(&self) -> String {
         env!("CARGO_PKG_VERSION").into()
     }
 
-    fn commands(&self) -> Vec<Box<dyn nu_plugin::PluginCommand<Plugin = Self>>> {
+    fn commands(&self) -> Vec<Box<dyn PluginCommand<Plugin = Self>>> {
         vec![
             Box::new(FromFasta),
             Box::new(FromFastaGz),
@@ -21,26 +29,43 @@ impl Plugin for BioPlugin {
             Box::new(FromFastqGz),
             Box::new(FromFq),
             Box::new(FromFqGz),
+            Box::new(Faidx),
             Box::new(ToFasta),
+            Box::new(ToFastaGz),
             Box::new(ToFastq),
+            Box::new(ToFastqGz),
             Box::new(FromBam),
+            Box::new(ToBam),
+            Box::new(QueryBam),
             Box::new(FromSam),
+            Box::new(ToSam),
             Box::new(FromCram),
+            Box::new(QueryCram),
             Box::new(FromBcf),
             Box::new(FromBcfGz),
+            Box::new(ToBcf),
             Box::new(FromVcf),
             Box::new(FromVcfGz),
+            Box::new(QueryVcf),
+            Box::new(ToVcf),
+            Box::new(ToVcfGz),
             Box::new(FromGff),
+            Box::new(QueryGff),
+            Box::new(ToGff),
             Box::new(FromGfa),
             Box::new(FromGfaGz),
+            Box::new(ToGfa),
             Box::new(FromBed),
+            Box::new(QueryBed),
+            Box::new(ToBed),
+            Box::new(Index),
         ]
     }
 }
 
 pub struct FromFasta;
 
-impl SimplePluginCommand for FromFasta {
+impl PluginCommand for FromFasta {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -65,18 +90,18 @@ impl SimplePluginCommand for FromFasta {
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_fasta(call, input, Compression::Uncompressed)
+        bio.from_fasta(call, engine.signals(), input, Compression::Uncompressed)
     }
 }
 
 pub struct FromFastaGz;
 
-impl SimplePluginCommand for FromFastaGz {
+impl PluginCommand for FromFastaGz {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -101,18 +126,18 @@ impl SimplePluginCommand for FromFastaGz {
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_fasta(call, input, Compression::Gzipped)
+        bio.from_fasta(call, engine.signals(), input, Compression::Gzipped)
     }
 }
 
 pub struct FromFa;
 
-impl SimplePluginCommand for FromFa {
+impl PluginCommand for FromFa {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -137,18 +162,18 @@ impl SimplePluginCommand for FromFa {
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_fasta(call, input, Compression::Uncompressed)
+        bio.from_fasta(call, engine.signals(), input, Compression::Uncompressed)
     }
 }
 
 pub struct FromFaGz;
 
-impl SimplePluginCommand for FromFaGz {
+impl PluginCommand for FromFaGz {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -173,18 +198,18 @@ impl SimplePluginCommand for FromFaGz {
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_fasta(call, input, Compression::Gzipped)
+        bio.from_fasta(call, engine.signals(), input, Compression::Gzipped)
     }
 }
 
 pub struct FromFastq;
 
-impl SimplePluginCommand for FromFastq {
+impl PluginCommand for FromFastq {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -214,18 +239,18 @@ impl SimplePluginCommand for FromFastq {
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_fastq(call, input, Compression::Uncompressed)
+        bio.from_fastq(call, engine.signals(), input, Compression::Uncompressed)
     }
 }
 
 pub struct FromFastqGz;
 
-impl SimplePluginCommand for FromFastqGz {
+impl PluginCommand for FromFastqGz {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -255,18 +280,18 @@ impl SimplePluginCommand for FromFastqGz {
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_fastq(call, input, Compression::Gzipped)
+        bio.from_fastq(call, engine.signals(), input, Compression::Gzipped)
     }
 }
 
 pub struct FromFq;
 
-impl SimplePluginCommand for FromFq {
+impl PluginCommand for FromFq {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -296,18 +321,18 @@ impl SimplePluginCommand for FromFq {
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_fastq(call, input, Compression::Uncompressed)
+        bio.from_fastq(call, engine.signals(), input, Compression::Uncompressed)
     }
 }
 
 pub struct FromFqGz;
 
-impl SimplePluginCommand for FromFqGz {
+impl PluginCommand for FromFqGz {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -337,18 +362,68 @@ impl SimplePluginCommand for FromFqGz {
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_fastq(call, input, Compression::Gzipped)
+        bio.from_fastq(call, engine.signals(), input, Compression::Gzipped)
+    }
+}
+
+pub struct Faidx;
+
+impl PluginCommand for Faidx {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "bio faidx"
+    }
+
+    fn description(&self) -> &str {
+        "Fetch regions (name:start-end) from an indexed fasta.\nUses the .fai next to the file, and the .gzi for BGZF compressed fasta. Regions can also be piped in, as strings or a table with a region column, or BED columns."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .required("path", SyntaxShape::Filepath, "the indexed fasta file")
+            .rest(
+                "region",
+                SyntaxShape::String,
+                "the regions to fetch, as name, name:start or name:start-end (1-based, inclusive)",
+            )
+            .input_output_types(vec![
+                (Type::Nothing, Type::Table(vec![].into())),
+                (Type::String, Type::Table(vec![].into())),
+                (
+                    Type::List(Box::new(Type::String)),
+                    Type::Table(vec![].into()),
+                ),
+                (Type::Table(vec![].into()), Type::Table(vec![].into())),
+            ])
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let path = resolve_path(engine, &call.req::<String>(0)?)?;
+
+        let mut regions = call.rest::<String>(1)?;
+        regions.extend(regions_from_value(&input.into_value(call.head)?)?);
+
+        let bio = Bio;
+        bio.faidx(call, engine.signals(), path, regions)
     }
 }
 
 pub struct ToFasta;
 
-impl SimplePluginCommand for ToFasta {
+impl PluginCommand for ToFasta {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -361,6 +436,18 @@ impl SimplePluginCommand for ToFasta {
 
     fn signature(&self) -> Signature {
         Signature::build(self.name())
+            .named(
+                "line-width",
+                SyntaxShape::Int,
+                "bases per sequence line, 0 for a single line (default 80)",
+                Some('w'),
+            )
+            .named(
+                "header-template",
+                SyntaxShape::String,
+                "build the header line from columns, e.g. \"{id} len={length}\"",
+                Some('t'),
+            )
             .input_output_type(Type::Table(vec![].into()), Type::String)
     }
 
@@ -369,16 +456,62 @@ impl SimplePluginCommand for ToFasta {
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
+        let bio = Bio;
+        bio.to_fasta(call, &input, Compression::Uncompressed)
+            .map(|v| PipelineData::Value(v, None))
+    }
+}
+
+pub struct ToFastaGz;
+
+impl PluginCommand for ToFastaGz {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to fasta.gz"
+    }
+
+    fn description(&self) -> &str {
+        "Print a parsed fasta object as BGZF compressed binary, which can be indexed with a .gzi"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .named(
+                "line-width",
+                SyntaxShape::Int,
+                "bases per sequence line, 0 for a single line (default 80)",
+                Some('w'),
+            )
+            .named(
+                "header-template",
+                SyntaxShape::String,
+                "build the header line from columns, e.g. \"{id} len={length}\"",
+                Some('t'),
+            )
+            .input_output_type(Type::Table(vec![].into()), Type::Binary)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
         let bio = Bio;
-        bio.to_fasta(call, input)
+        bio.to_fasta(call, &input, Compression::Gzipped)
+            .map(|v| PipelineData::Value(v, None))
     }
 }
 
 pub struct ToFastq;
 
-impl SimplePluginCommand for ToFastq {
+impl PluginCommand for ToFastq {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -390,8 +523,7 @@ impl SimplePluginCommand for ToFastq {
     }
 
     fn signature(&self) -> Signature {
-        Signature::build(self.name())
-            .input_output_type(Type::Table(vec![].into()), Type::String)
+        Signature::build(self.name()).input_output_type(Type::Table(vec![].into()), Type::String)
     }
 
     fn run(
@@ -399,16 +531,49 @@ impl SimplePluginCommand for ToFastq {
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
         let bio = Bio;
-        bio.to_fastq(call, input)
+        bio.to_fastq(call, &input, Compression::Uncompressed)
+            .map(|v| PipelineData::Value(v, None))
+    }
+}
+
+pub struct ToFastqGz;
+
+impl PluginCommand for ToFastqGz {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to fastq.gz"
+    }
+
+    fn description(&self) -> &str {
+        "Print out a fastq from structured nuon as BGZF compressed binary"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name()).input_output_type(Type::Table(vec![].into()), Type::Binary)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
+        let bio = Bio;
+        bio.to_fastq(call, &input, Compression::Gzipped)
+            .map(|v| PipelineData::Value(v, None))
     }
 }
 
 pub struct FromBam;
 
-impl SimplePluginCommand for FromBam {
+impl PluginCommand for FromBam {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -421,7 +586,62 @@ impl SimplePluginCommand for FromBam {
 
     fn signature(&self) -> Signature {
         Signature::build(self.name())
-            .input_output_type(Type::Binary, Type::Record(vec![].into()))
+            .switch(
+                "stream",
+                "stream the body records lazily, without the header",
+                Some('s'),
+            )
+            .switch(
+                "phred-ints",
+                "return quality scores as a list of Phred ints",
+                Some('p'),
+            )
+            .switch(
+                "raw-tags",
+                "keep the auxiliary tags as tab separated SAM text",
+                Some('r'),
+            )
+            .input_output_types(vec![
+                (Type::Binary, Type::Record(vec![].into())),
+                (Type::Binary, Type::Table(vec![].into())),
+            ])
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let bio = Bio;
+        bio.from_bam(call, engine.signals(), input)
+    }
+}
+
+pub struct ToBam;
+
+impl PluginCommand for ToBam {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to bam"
+    }
+
+    fn description(&self) -> &str {
+        "Encode a parsed SAM/BAM record (header and body) as BGZF compressed BAM"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .named(
+                "compression-level",
+                SyntaxShape::Int,
+                "the BGZF compression level, from 0 (none) to 9 (best)",
+                Some('c'),
+            )
+            .input_output_type(Type::Record(vec![].into()), Type::Binary)
             .category(Category::Experimental)
     }
 
@@ -430,16 +650,71 @@ impl SimplePluginCommand for FromBam {
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let compression_level = call.get_flag::<i64>("compression-level")?;
+        let input = input.into_value(call.head)?;
         let bio = Bio;
-        bio.from_bam(call, input)
+        bio.to_bam(call, &input, compression_level)
+            .map(|v| PipelineData::Value(v, None))
+    }
+}
+
+pub struct QueryBam;
+
+impl PluginCommand for QueryBam {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "bio query bam"
+    }
+
+    fn description(&self) -> &str {
+        "Query an indexed, coordinate sorted BAM file for a region.\nUses the .bai (or .csi) next to the file, and returns the overlapping alignments."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .required("path", SyntaxShape::Filepath, "the BAM file to query")
+            .required_named(
+                "region",
+                SyntaxShape::String,
+                "the region to query, as chr:start-end (1-based, inclusive)",
+                None,
+            )
+            .switch(
+                "phred-ints",
+                "return quality scores as a list of Phred ints",
+                Some('p'),
+            )
+            .switch(
+                "raw-tags",
+                "keep the auxiliary tags as tab separated SAM text",
+                Some('r'),
+            )
+            .input_output_type(Type::Nothing, Type::Table(vec![].into()))
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let path = resolve_path(engine, &call.req::<String>(0)?)?;
+        let region = call
+            .get_flag::<String>("region")?
+            .ok_or_else(|| LabeledError::new("A --region to query is required"))?;
+        let bio = Bio;
+        bio.query_bam(call, engine.signals(), path, &region)
     }
 }
 
 pub struct FromSam;
 
-impl SimplePluginCommand for FromSam {
+impl PluginCommand for FromSam {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -452,7 +727,56 @@ impl SimplePluginCommand for FromSam {
 
     fn signature(&self) -> Signature {
         Signature::build(self.name())
-            .input_output_type(Type::Binary, Type::Record(vec![].into()))
+            .switch(
+                "stream",
+                "stream the body records lazily, without the header",
+                Some('s'),
+            )
+            .switch(
+                "phred-ints",
+                "return quality scores as a list of Phred ints",
+                Some('p'),
+            )
+            .switch(
+                "raw-tags",
+                "keep the auxiliary tags as tab separated SAM text",
+                Some('r'),
+            )
+            .input_output_types(vec![
+                (Type::Binary, Type::Record(vec![].into())),
+                (Type::Binary, Type::Table(vec![].into())),
+            ])
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let bio = Bio;
+        bio.from_sam(call, engine.signals(), input)
+    }
+}
+
+pub struct ToSam;
+
+impl PluginCommand for ToSam {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to sam"
+    }
+
+    fn description(&self) -> &str {
+        "Print a parsed SAM/BAM record (header and body) out as SAM text"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .input_output_type(Type::Record(vec![].into()), Type::String)
             .category(Category::Experimental)
     }
 
@@ -461,16 +785,18 @@ impl SimplePluginCommand for FromSam {
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
         let bio = Bio;
-        bio.from_sam(call, input)
+        bio.to_sam(call, &input)
+            .map(|v| PipelineData::Value(v, None))
     }
 }
 
 pub struct FromCram;
 
-impl SimplePluginCommand for FromCram {
+impl PluginCommand for FromCram {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -483,25 +809,117 @@ impl SimplePluginCommand for FromCram {
 
     fn signature(&self) -> Signature {
         Signature::build(self.name())
-            .input_output_type(Type::Binary, Type::Record(vec![].into()))
+            .switch(
+                "stream",
+                "stream the body records lazily, without the header",
+                Some('s'),
+            )
+            .switch(
+                "phred-ints",
+                "return quality scores as a list of Phred ints",
+                Some('p'),
+            )
+            .switch(
+                "raw-tags",
+                "keep the auxiliary tags as tab separated SAM text",
+                Some('r'),
+            )
+            .named(
+                "reference",
+                SyntaxShape::Filepath,
+                "the reference fasta the CRAM was compressed against",
+                Some('R'),
+            )
+            .input_output_types(vec![
+                (Type::Binary, Type::Record(vec![].into())),
+                (Type::Binary, Type::Table(vec![].into())),
+            ])
             .category(Category::Experimental)
     }
 
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let reference = match call.get_flag::<String>("reference")? {
+            Some(path) => Some(resolve_path(engine, &path)?),
+            None => None,
+        };
+
+        let bio = Bio;
+        bio.from_cram(call, engine.signals(), input, reference)
+    }
+}
+
+pub struct QueryCram;
+
+impl PluginCommand for QueryCram {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "bio query cram"
+    }
+
+    fn description(&self) -> &str {
+        "Query an indexed, coordinate sorted CRAM file for a region.\nUses the .crai next to the file, and only decodes the containers and slices overlapping the region."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .required("path", SyntaxShape::Filepath, "the CRAM file to query")
+            .required_named(
+                "region",
+                SyntaxShape::String,
+                "the region to query, as chr:start-end (1-based, inclusive)",
+                None,
+            )
+            .named(
+                "reference",
+                SyntaxShape::Filepath,
+                "the reference fasta the CRAM was compressed against",
+                Some('R'),
+            )
+            .switch(
+                "phred-ints",
+                "return quality scores as a list of Phred ints",
+                Some('p'),
+            )
+            .switch(
+                "raw-tags",
+                "keep the auxiliary tags as tab separated SAM text",
+                Some('r'),
+            )
+            .input_output_type(Type::Nothing, Type::Table(vec![].into()))
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let path = resolve_path(engine, &call.req::<String>(0)?)?;
+        let region = call
+            .get_flag::<String>("region")?
+            .ok_or_else(|| LabeledError::new("A --region to query is required"))?;
+        let reference = match call.get_flag::<String>("reference")? {
+            Some(path) => Some(resolve_path(engine, &path)?),
+            None => None,
+        };
+
         let bio = Bio;
-        bio.from_cram(call, input)
+        bio.query_cram(call, engine.signals(), path, &region, reference)
     }
 }
 
 pub struct FromBcf;
 
-impl SimplePluginCommand for FromBcf {
+impl PluginCommand for FromBcf {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -514,25 +932,34 @@ impl SimplePluginCommand for FromBcf {
 
     fn signature(&self) -> Signature {
         Signature::build(self.name())
-            .input_output_type(Type::Binary, Type::Record(vec![].into()))
+            .switch(
+                "stream",
+                "stream the body records lazily, without the header",
+                Some('s'),
+            )
+            .switch("long", "emit one row per variant and sample", Some('l'))
+            .input_output_types(vec![
+                (Type::Binary, Type::Record(vec![].into())),
+                (Type::Binary, Type::Table(vec![].into())),
+            ])
             .category(Category::Experimental)
     }
 
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_bcf(call, input, Compression::Uncompressed)
+        bio.from_bcf(call, engine.signals(), input, Compression::Uncompressed)
     }
 }
 
 pub struct FromBcfGz;
 
-impl SimplePluginCommand for FromBcfGz {
+impl PluginCommand for FromBcfGz {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -545,7 +972,47 @@ impl SimplePluginCommand for FromBcfGz {
 
     fn signature(&self) -> Signature {
         Signature::build(self.name())
-            .input_output_type(Type::Binary, Type::Record(vec![].into()))
+            .switch(
+                "stream",
+                "stream the body records lazily, without the header",
+                Some('s'),
+            )
+            .switch("long", "emit one row per variant and sample", Some('l'))
+            .input_output_types(vec![
+                (Type::Binary, Type::Record(vec![].into())),
+                (Type::Binary, Type::Table(vec![].into())),
+            ])
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let bio = Bio;
+        bio.from_bcf(call, engine.signals(), input, Compression::Gzipped)
+    }
+}
+
+pub struct ToBcf;
+
+impl PluginCommand for ToBcf {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to bcf"
+    }
+
+    fn description(&self) -> &str {
+        "Encode a parsed VCF/BCF record (header and body) as BCF"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .input_output_type(Type::Record(vec![].into()), Type::Binary)
             .category(Category::Experimental)
     }
 
@@ -554,16 +1021,18 @@ impl SimplePluginCommand for FromBcfGz {
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
         let bio = Bio;
-        bio.from_bcf(call, input, Compression::Gzipped)
+        bio.to_bcf(call, &input)
+            .map(|v| PipelineData::Value(v, None))
     }
 }
 
 pub struct FromVcf;
 
-impl SimplePluginCommand for FromVcf {
+impl PluginCommand for FromVcf {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -576,25 +1045,34 @@ impl SimplePluginCommand for FromVcf {
 
     fn signature(&self) -> Signature {
         Signature::build(self.name())
-            .input_output_type(Type::Binary, Type::Record(vec![].into()))
+            .switch(
+                "stream",
+                "stream the body records lazily, without the header",
+                Some('s'),
+            )
+            .switch("long", "emit one row per variant and sample", Some('l'))
+            .input_output_types(vec![
+                (Type::Binary, Type::Record(vec![].into())),
+                (Type::Binary, Type::Table(vec![].into())),
+            ])
             .category(Category::Experimental)
     }
 
     fn run(
         &self,
         _plugin: &BioPlugin,
-        _engine: &EngineInterface,
+        engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
-        bio.from_vcf(call, input, Compression::Uncompressed)
+        bio.from_vcf(call, engine.signals(), input, Compression::Uncompressed)
     }
 }
 
 pub struct FromVcfGz;
 
-impl SimplePluginCommand for FromVcfGz {
+impl PluginCommand for FromVcfGz {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -607,7 +1085,123 @@ impl SimplePluginCommand for FromVcfGz {
 
     fn signature(&self) -> Signature {
         Signature::build(self.name())
-            .input_output_type(Type::Binary, Type::Record(vec![].into()))
+            .switch(
+                "stream",
+                "stream the body records lazily, without the header",
+                Some('s'),
+            )
+            .switch("long", "emit one row per variant and sample", Some('l'))
+            .input_output_types(vec![
+                (Type::Binary, Type::Record(vec![].into())),
+                (Type::Binary, Type::Table(vec![].into())),
+            ])
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let bio = Bio;
+        bio.from_vcf(call, engine.signals(), input, Compression::Gzipped)
+    }
+}
+
+pub struct QueryVcf;
+
+impl PluginCommand for QueryVcf {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "bio query vcf"
+    }
+
+    fn description(&self) -> &str {
+        "Query a BGZF compressed, tabix indexed VCF file for a region.\nUses the .tbi (or .csi) next to the file, and returns the overlapping variants."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .required("path", SyntaxShape::Filepath, "the VCF file to query")
+            .required_named(
+                "region",
+                SyntaxShape::String,
+                "the region to query, as chr:start-end (1-based, inclusive)",
+                None,
+            )
+            .switch("long", "emit one row per variant and sample", Some('l'))
+            .input_output_type(Type::Nothing, Type::Table(vec![].into()))
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let path = resolve_path(engine, &call.req::<String>(0)?)?;
+        let region = call
+            .get_flag::<String>("region")?
+            .ok_or_else(|| LabeledError::new("A --region to query is required"))?;
+        let bio = Bio;
+        bio.query_vcf(call, engine.signals(), path, &region)
+    }
+}
+
+pub struct ToVcf;
+
+impl PluginCommand for ToVcf {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to vcf"
+    }
+
+    fn description(&self) -> &str {
+        "Print a parsed VCF/BCF record (header and body) out as VCF text"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .input_output_type(Type::Record(vec![].into()), Type::String)
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
+        let bio = Bio;
+        bio.to_vcf(call, &input, Compression::Uncompressed)
+            .map(|v| PipelineData::Value(v, None))
+    }
+}
+
+pub struct ToVcfGz;
+
+impl PluginCommand for ToVcfGz {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to vcf.gz"
+    }
+
+    fn description(&self) -> &str {
+        "Encode a parsed VCF/BCF record (header and body) as BGZF compressed VCF, which tabix can index"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .input_output_type(Type::Record(vec![].into()), Type::Binary)
             .category(Category::Experimental)
     }
 
@@ -616,16 +1210,18 @@ impl SimplePluginCommand for FromVcfGz {
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
         let bio = Bio;
-        bio.from_vcf(call, input, Compression::Gzipped)
+        bio.to_vcf(call, &input, Compression::Gzipped)
+            .map(|v| PipelineData::Value(v, None))
     }
 }
 
 pub struct FromGff;
 
-impl SimplePluginCommand for FromGff {
+impl PluginCommand for FromGff {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -633,12 +1229,96 @@ impl SimplePluginCommand for FromGff {
     }
 
     fn description(&self) -> &str {
-        "Parse a GFF file.\nReturns a table."
+        "Parse a GFF file.\nReturns a record containing the header, the body of features, and any sequences from the ##FASTA section."
     }
 
     fn signature(&self) -> Signature {
         Signature::build(self.name())
-            .input_output_type(Type::Binary, Type::Table(vec![].into()))
+            .switch(
+                "stream",
+                "stream the body records lazily, without the header",
+                Some('s'),
+            )
+            .input_output_types(vec![
+                (Type::Binary, Type::Record(vec![].into())),
+                (Type::Binary, Type::Table(vec![].into())),
+            ])
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let bio = Bio;
+        bio.from_gff(call, engine.signals(), input)
+    }
+}
+
+pub struct QueryGff;
+
+impl PluginCommand for QueryGff {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "bio query gff"
+    }
+
+    fn description(&self) -> &str {
+        "Query a BGZF compressed, tabix indexed GFF file for a region.\nUses the .tbi (or .csi) next to the file, and returns the overlapping features."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .required("path", SyntaxShape::Filepath, "the GFF file to query")
+            .required_named(
+                "region",
+                SyntaxShape::String,
+                "the region to query, as chr:start-end (1-based, inclusive)",
+                None,
+            )
+            .input_output_type(Type::Nothing, Type::Table(vec![].into()))
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let path = resolve_path(engine, &call.req::<String>(0)?)?;
+        let region = call
+            .get_flag::<String>("region")?
+            .ok_or_else(|| LabeledError::new("A --region to query is required"))?;
+        let bio = Bio;
+        bio.query_gff(call, engine.signals(), path, &region)
+    }
+}
+
+pub struct ToGff;
+
+impl PluginCommand for ToGff {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to gff"
+    }
+
+    fn description(&self) -> &str {
+        "Print a parsed GFF3 record (header, body and fasta), or a table of features, out as GFF3"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .input_output_types(vec![
+                (Type::Record(vec![].into()), Type::String),
+                (Type::Table(vec![].into()), Type::String),
+            ])
             .category(Category::Experimental)
     }
 
@@ -647,16 +1327,18 @@ impl SimplePluginCommand for FromGff {
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
         let bio = Bio;
-        bio.from_gff(call, input)
+        bio.to_gff(call, &input)
+            .map(|v| PipelineData::Value(v, None))
     }
 }
 
 pub struct FromGfa;
 
-impl SimplePluginCommand for FromGfa {
+impl PluginCommand for FromGfa {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -678,16 +1360,17 @@ impl SimplePluginCommand for FromGfa {
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
         bio.from_gfa(call, input, Compression::Uncompressed)
+            .map(|v| PipelineData::Value(v, None))
     }
 }
 
 pub struct FromGfaGz;
 
-impl SimplePluginCommand for FromGfaGz {
+impl PluginCommand for FromGfaGz {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -709,16 +1392,50 @@ impl SimplePluginCommand for FromGfaGz {
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
         let bio = Bio;
         bio.from_gfa(call, input, Compression::Gzipped)
+            .map(|v| PipelineData::Value(v, None))
+    }
+}
+
+pub struct ToGfa;
+
+impl PluginCommand for ToGfa {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to gfa"
+    }
+
+    fn description(&self) -> &str {
+        "Print a parsed GFA record (header, segments, links, containments and paths) out as GFA 1.0"
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .input_output_type(Type::Record(vec![].into()), Type::String)
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        _engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
+        let bio = Bio;
+        bio.to_gfa(call, &input)
+            .map(|v| PipelineData::Value(v, None))
     }
 }
 
 pub struct FromBed;
 
-impl SimplePluginCommand for FromBed {
+impl PluginCommand for FromBed {
     type Plugin = BioPlugin;
 
     fn name(&self) -> &str {
@@ -735,14 +1452,134 @@ impl SimplePluginCommand for FromBed {
             .category(Category::Experimental)
     }
 
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let bio = Bio;
+        bio.from_bed(call, engine.signals(), input)
+    }
+}
+
+pub struct QueryBed;
+
+impl PluginCommand for QueryBed {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "bio query bed"
+    }
+
+    fn description(&self) -> &str {
+        "Query a BGZF compressed, tabix indexed BED file for a region.\nUses the .tbi (or .csi) next to the file, and returns the overlapping intervals."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .required("path", SyntaxShape::Filepath, "the BED file to query")
+            .required_named(
+                "region",
+                SyntaxShape::String,
+                "the region to query, as chr:start-end (1-based, inclusive)",
+                None,
+            )
+            .input_output_type(Type::Nothing, Type::Table(vec![].into()))
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let path = resolve_path(engine, &call.req::<String>(0)?)?;
+        let region = call
+            .get_flag::<String>("region")?
+            .ok_or_else(|| LabeledError::new("A --region to query is required"))?;
+        let bio = Bio;
+        bio.query_bed(call, engine.signals(), path, &region)
+    }
+}
+
+pub struct ToBed;
+
+impl PluginCommand for ToBed {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "to bed"
+    }
+
+    fn description(&self) -> &str {
+        "Print a table of BED records out as a BED file.\nWrites as many of the standard columns (BED3 to BED12) as the table has."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .input_output_type(Type::Table(vec![].into()), Type::String)
+            .category(Category::Experimental)
+    }
+
     fn run(
         &self,
         _plugin: &BioPlugin,
         _engine: &EngineInterface,
         call: &EvaluatedCall,
-        input: &Value,
-    ) -> Result<Value, LabeledError> {
+        input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let input = input.into_value(call.head)?;
+        let bio = Bio;
+        bio.to_bed(call, &input)
+            .map(|v| PipelineData::Value(v, None))
+    }
+}
+
+pub struct Index;
+
+impl PluginCommand for Index {
+    type Plugin = BioPlugin;
+
+    fn name(&self) -> &str {
+        "bio index"
+    }
+
+    fn description(&self) -> &str {
+        "Build the index of a file, next to it.\nWrites a .fai (and .gzi) for fasta, a .bai or .csi for coordinate sorted BAM, a .crai for CRAM, and a .tbi for BGZF compressed VCF, BED and GFF. Returns the index records as a table."
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::build(self.name())
+            .required("path", SyntaxShape::Filepath, "the file to index")
+            .switch("csi", "write a .csi rather than a .bai for BAM", Some('c'))
+            .named(
+                "reference",
+                SyntaxShape::Filepath,
+                "the reference fasta a CRAM was compressed against",
+                Some('R'),
+            )
+            .input_output_type(Type::Nothing, Type::Table(vec![].into()))
+            .category(Category::Experimental)
+    }
+
+    fn run(
+        &self,
+        _plugin: &BioPlugin,
+        engine: &EngineInterface,
+        call: &EvaluatedCall,
+        _input: PipelineData,
+    ) -> Result<PipelineData, LabeledError> {
+        let path = resolve_path(engine, &call.req::<String>(0)?)?;
+        let reference = match call.get_flag::<String>("reference")? {
+            Some(path) => Some(resolve_path(engine, &path)?),
+            None => None,
+        };
         let bio = Bio;
-        bio.from_bed(call, input.clone())
+        bio.index(call, path, reference)
+            .map(|v| PipelineData::Value(v, None))
     }
 }